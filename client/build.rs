@@ -0,0 +1,18 @@
+use std::process::Command;
+
+// The commit the client was built from, sent to the server in the handshake.
+// Left unset outside a git checkout, the client then sends its version.
+fn main() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output();
+    if let Ok(output) = output {
+        let hash = String::from_utf8_lossy(&output.stdout);
+        if output.status.success() && !hash.trim().is_empty() {
+            println!("cargo:rustc-env=GIT_HASH={}", hash.trim());
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::player::Player;
+use game_channel::{ChannelVector2, ChannelVector3, PlayerId};
+use game_core::weapon::WeaponKind;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// ----------------------------------------------------------------------------
+// Remote players
+// Snapshots arrive in bursts, so enemies are drawn a little in the past where
+// there is usually a snapshot on either side to blend between. When the next
+// one is late the enemy keeps moving the way it was going for a short while.
+// ----------------------------------------------------------------------------
+
+// How far past the newest snapshot an enemy keeps moving before it stops
+const MAX_EXTRAPOLATION: f64 = 0.25;
+// Older snapshots are never needed again once the delay is this long
+const MAX_SNAPSHOTS: usize = 64;
+// How quickly the clock estimate follows snapshots that arrive later than usual
+const CLOCK_SMOOTHING: f64 = 0.05;
+
+#[derive(Clone, Copy)]
+struct Snapshot {
+    time: f64,
+    pos: ChannelVector3,
+    target: ChannelVector2,
+}
+
+struct Enemy {
+    player: Player,
+    snapshots: VecDeque<Snapshot>,
+}
+
+// Every other player in the match, by the id the server gave them
+pub struct Enemies {
+    enemies: HashMap<PlayerId, Enemy>,
+    start: Instant,
+    // Server time minus local time, None until the first snapshot
+    clock_offset: Option<f64>,
+    newest_snapshot: f64,
+}
+
+impl Default for Enemies {
+    fn default() -> Self {
+        Enemies {
+            enemies: HashMap::new(),
+            start: Instant::now(),
+            clock_offset: None,
+            newest_snapshot: 0.0,
+        }
+    }
+}
+
+impl Enemies {
+    // Buffer a snapshot taken at the given server time
+    pub fn update(
+        &mut self,
+        id: PlayerId,
+        pos: ChannelVector3,
+        target: ChannelVector2,
+        time: f64,
+        weapon: WeaponKind,
+        max_health: u8,
+    ) {
+        self.observe_clock(time);
+        self.newest_snapshot = self.newest_snapshot.max(time);
+
+        let enemy = self.enemies.entry(id).or_insert_with(|| {
+            let mut player = Player::default();
+            player.set_max_health(max_health);
+            player.set_stats(pos, target);
+            Enemy {
+                player,
+                snapshots: VecDeque::new(),
+            }
+        });
+
+        // Over UDP a snapshot can arrive after a newer one
+        if enemy.snapshots.back().is_some_and(|last| last.time >= time) {
+            return;
+        }
+        enemy.player.equip(weapon);
+        enemy.snapshots.push_back(Snapshot { time, pos, target });
+        if enemy.snapshots.len() > MAX_SNAPSHOTS {
+            enemy.snapshots.pop_front();
+        }
+    }
+
+    // Move every enemy to where it was the given delay ago, in server time
+    pub fn interpolate(&mut self, delay: Duration) {
+        let Some(offset) = self.clock_offset else {
+            return;
+        };
+        let render_time = self.local_time() + offset - delay.as_secs_f64();
+
+        for enemy in self.enemies.values_mut() {
+            // Keep one snapshot from before the render time to blend from
+            while enemy.snapshots.len() > 2 && enemy.snapshots[1].time <= render_time {
+                enemy.snapshots.pop_front();
+            }
+            if let Some((pos, target)) = sample(&enemy.snapshots, render_time) {
+                enemy.player.set_stats(pos, target);
+            }
+        }
+    }
+
+    // Lets the server work out what the player was looking at when shooting
+    pub fn newest_snapshot(&self) -> f64 {
+        self.newest_snapshot
+    }
+
+    pub fn get_mut(&mut self, id: &PlayerId) -> Option<&mut Player> {
+        self.enemies.get_mut(id).map(|enemy| &mut enemy.player)
+    }
+
+    pub fn remove(&mut self, id: &PlayerId) {
+        self.enemies.remove(id);
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Player> {
+        self.enemies.values().map(|enemy| &enemy.player)
+    }
+
+    fn local_time(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    // The quickest snapshot so far had the least delay on the way, so jump to
+    // anything faster and only drift slowly towards slower ones
+    fn observe_clock(&mut self, server_time: f64) {
+        let sample = server_time - self.local_time();
+        self.clock_offset = Some(match self.clock_offset {
+            Some(offset) if sample < offset => offset + (sample - offset) * CLOCK_SMOOTHING,
+            _ => sample,
+        });
+    }
+}
+
+// Position and target at the given time, blending between the two snapshots
+// around it or running on from the last two
+fn sample(snapshots: &VecDeque<Snapshot>, time: f64) -> Option<(ChannelVector3, ChannelVector2)> {
+    let last = *snapshots.back()?;
+    let (from, to) = match snapshots.iter().position(|snapshot| snapshot.time > time) {
+        Some(0) => return Some((snapshots[0].pos, snapshots[0].target)),
+        Some(i) => (snapshots[i - 1], snapshots[i]),
+        None if snapshots.len() >= 2 => (snapshots[snapshots.len() - 2], last),
+        None => return Some((last.pos, last.target)),
+    };
+
+    let time = time.min(last.time + MAX_EXTRAPOLATION);
+    let t = ((time - from.time) / (to.time - from.time)) as f32;
+    Some((from.pos.lerp(to.pos, t), from.target.lerp(to.target, t)))
+}
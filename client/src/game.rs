@@ -4,15 +4,26 @@ https://doc.rust-lang.org/book/ch17-03-oo-design-patterns.html
 */
 
 use crate::button::Button;
+use crate::enemies::Enemies;
+use crate::handler::PacketHandler;
 use crate::input_box::InputBox;
 use crate::map::Map;
 use crate::object::Drawable3D;
 use crate::player::Player;
-use game_channel::{Channel, Packet, Winner};
+use game_channel::error::ChannelError;
+use game_channel::udp::UdpChannel;
+use game_channel::{
+    Channel, ChannelVector2, ChannelVector3, MapInfo, Packet, PlayerId, Rules, Transport, Winner,
+};
+use game_core::map::Layout;
+use game_core::movement::{Input, MoveState};
+use game_core::weapon::WeaponKind;
 use raylib::audio::RaylibAudio;
 use raylib::core::texture::Image;
 use raylib::prelude::*;
-use std::net::{Shutdown, TcpStream};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 pub struct Game {
     state: Option<Box<dyn GameState>>,
@@ -37,7 +48,7 @@ impl Game {
         rl.set_target_fps(60);
 
         Game {
-            state: Some(Box::new(LobbyState::new(rl, thread, Winner::None))),
+            state: Some(Box::new(LobbyState::new(rl, thread))),
         }
     }
 
@@ -56,31 +67,73 @@ trait GameState {
     fn run(self: Box<Self>) -> Option<Box<dyn GameState>>;
 }
 
+// The server picks TCP or UDP during the handshake
+type Connection = Box<dyn Transport>;
+
+// Identifies this build to the server, set by build.rs
+const CLIENT_BUILD: &str = match option_env!("GIT_HASH") {
+    Some(hash) => hash,
+    None => env!("CARGO_PKG_VERSION"),
+};
+
+// Orbits the map while not playing
+fn overview_camera() -> Camera3D {
+    let pos = Vector3::new(20.0, 13.0, 20.0);
+    let target = Vector3::zero();
+    let up = Vector3::up();
+    let fovy = 60.0;
+    Camera3D::perspective(pos, target, up, fovy)
+}
+
+// Playing on other walls than the server would only get us corrected all the
+// time, better not to play at all
+fn build_map(map: &MapInfo) -> Result<Map, String> {
+    Layout::parse(&map.source)
+        .map(|layout| Map::new(&layout))
+        .map_err(|e| format!("Map {} from the server is broken: {}", map.name, e))
+}
+
+fn interpolation_delay(rules: &Rules) -> Duration {
+    Duration::from_millis(rules.interpolation_delay as u64)
+}
+
 struct LobbyState {
     rl: RaylibHandle,
     thread: RaylibThread,
+    name_box: InputBox,
     input_box: InputBox,
     play_button: Button,
     quit_button: Button,
-    map: Map,
+    // Only the backdrop, the server sends the map to play on. None when the
+    // map file is broken, the message says what is wrong.
+    map: Option<Map>,
     camera: Camera3D,
-    winner: Winner,
+    message: String,
 }
 
 impl LobbyState {
-    fn new(rl: RaylibHandle, thread: RaylibThread, winner: Winner) -> Self {
+    fn new(rl: RaylibHandle, thread: RaylibThread) -> Self {
         let x = Game::SCREEN_WIDTH / 2 - Button::WIDTH / 2;
         let play_y = Game::SCREEN_HEIGHT / 2 - Button::HEIGHT / 2;
         let quit_y = Game::SCREEN_HEIGHT / 2 + Button::HEIGHT / 2 + Button::SPACING;
+        let name_x = Game::SCREEN_WIDTH / 2 - InputBox::WIDTH / 2;
+        let name_y = play_y - (Button::SPACING + InputBox::HEIGHT) * 2;
 
-        // Camera
-        let pos = Vector3::new(20.0, 13.0, 20.0);
-        let target = Vector3::zero();
-        let up = Vector3::up();
-        let fovy = 60.0;
-        let camera = Camera3D::perspective(pos, target, up, fovy);
+        let (map, message) = match Map::load(Path::new(Map::DEFAULT_PATH)) {
+            Ok(map) => (Some(map), String::new()),
+            Err(e) => (None, format!("Map {} is broken: {}", Map::DEFAULT_PATH, e)),
+        };
 
         LobbyState {
+            name_box: InputBox::new(
+                Rectangle {
+                    x: name_x as f32,
+                    y: name_y as f32,
+                    width: InputBox::WIDTH as f32,
+                    height: InputBox::HEIGHT as f32,
+                },
+                String::from("Player name"),
+            ),
             input_box: InputBox::default(),
             play_button: Button::new(
                 Rectangle {
@@ -102,22 +155,69 @@ impl LobbyState {
             ),
             rl,
             thread,
-            map: Map::default(),
-            camera,
-            winner,
+            map,
+            camera: overview_camera(),
+            message,
+        }
+    }
+
+    // Back to the lobby after the server went away
+    fn disconnected(
+        rl: RaylibHandle,
+        thread: RaylibThread,
+        error: ChannelError,
+    ) -> Box<dyn GameState> {
+        let mut lobby = LobbyState::new(rl, thread);
+        lobby.message = format!("Lost connection to server: {}", error);
+        Box::new(lobby)
+    }
+
+    // Connect to the server and introduce ourselves, explaining any failure.
+    // The server says which map it plays on.
+    fn connect(&mut self) -> Result<(Connection, Map), String> {
+        let ip = self.input_box.get_text();
+        let stream =
+            TcpStream::connect(ip).map_err(|e| format!("Could not connect to {}: {}", ip, e))?;
+
+        // Don't hang the lobby on a server that never answers
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+
+        let mut channel = Channel::with_stream(stream);
+        let (udp_port, map) = match channel.handshake(self.name_box.get_text(), CLIENT_BUILD) {
+            Ok(reply) => reply,
+            Err(ChannelError::VersionMismatch { local, remote }) => {
+                return Err(format!(
+                    "Server refused connection: it runs protocol v{}, this client v{}",
+                    remote, local
+                ))
+            }
+            Err(e) => return Err(format!("Handshake with {} failed: {}", ip, e)),
+        };
+
+        let map = build_map(&map)?;
+
+        channel
+            .stream
+            .set_read_timeout(None)
+            .map_err(|e| e.to_string())?;
+
+        // The server decides whether the match runs over UDP
+        match udp_port {
+            Some(port) => match UdpChannel::connect(channel.stream, port) {
+                Ok(channel) => Ok((Box::new(channel), map)),
+                Err(e) => Err(format!("Could not reach {} over UDP: {}", ip, e)),
+            },
+            None => Ok((Box::new(channel), map)),
         }
     }
 }
 
 impl GameState for LobbyState {
     fn run(mut self: Box<Self>) -> Option<Box<dyn GameState>> {
-        let text = match self.winner {
-            Winner::Player => "YOU WON",
-            Winner::Enemy => "YOU LOSE",
-            Winner::None => "",
-        };
-        let text_width = self.rl.measure_text(text, 60);
-        let text_x = Game::SCREEN_WIDTH / 2 - text_width / 2;
+        let message_y = Game::SCREEN_HEIGHT / 2 + Button::HEIGHT * 3 / 2 + Button::SPACING * 2;
 
         loop {
             if self.rl.window_should_close() {
@@ -128,6 +228,7 @@ impl GameState for LobbyState {
             self.rl
                 .update_camera(&mut self.camera, CameraMode::CAMERA_ORBITAL);
 
+            self.name_box.update(&mut self.rl);
             self.input_box.update(&mut self.rl);
             self.play_button.update(&self.rl);
             self.quit_button.update(&self.rl);
@@ -137,35 +238,52 @@ impl GameState for LobbyState {
 
             d.clear_background(Color::SKYBLUE);
 
-            self.map.draw(&mut d, &self.camera);
+            if let Some(map) = &self.map {
+                map.draw(&mut d, &self.camera);
+            }
 
+            self.name_box.draw(&mut d);
             self.input_box.draw(&mut d);
             self.play_button.draw(&mut d);
             self.quit_button.draw(&mut d);
 
-            // Draw winner
-            d.draw_text(text, text_x, 100, 60, Color::BLACK);
+            // Draw why the last connection attempt failed
+            let message_width = d.measure_text(&self.message, Game::FONT_SIZE);
+            d.draw_text(
+                &self.message,
+                Game::SCREEN_WIDTH / 2 - message_width / 2,
+                message_y,
+                Game::FONT_SIZE,
+                Color::MAROON,
+            );
 
             drop(d);
 
             // Check if button is clicked
             if self.play_button.is_clicked() {
                 // Check if can connect to server
-                let ip = self.input_box.get_text();
-                if let Ok(stream) = TcpStream::connect(ip) {
-                    let mut player = Player::default();
-
-                    let mut channel = Channel::with_stream(stream);
-                    if let Ok(_) = player.read_stats(&mut channel) {
-                        break Some(Box::new(WaitState::new(
-                            self.rl,
-                            self.thread,
-                            channel,
-                            player,
-                            self.map,
-                            self.camera,
-                        )));
+                match self.connect() {
+                    Ok((mut channel, map)) => {
+                        // The server answers the handshake with our id and starting position
+                        if let Ok(Packet::Player {
+                            id, pos, target, ..
+                        }) = channel.receive()
+                        {
+                            let mut player = Player::default();
+                            player.set_stats(pos, target);
+                            break Some(Box::new(WaitState::new(
+                                self.rl,
+                                self.thread,
+                                channel,
+                                id,
+                                player,
+                                map,
+                                self.camera,
+                            )));
+                        }
+                        self.message = String::from("Server did not send a starting position");
                     }
+                    Err(message) => self.message = message,
                 }
                 self.play_button.toggle_clicked();
             }
@@ -180,9 +298,15 @@ impl GameState for LobbyState {
 struct WaitState {
     rl: RaylibHandle,
     thread: RaylibThread,
-    channel: Channel<TcpStream>,
+    channel: Connection,
+    id: PlayerId,
+    rules: Rules,
     player: Player,
+    enemies: Enemies,
+    time: Option<u8>,
     map: Map,
+    // Set when the server moved us to a map we cannot play on
+    map_error: Option<String>,
     camera: Camera3D,
 }
 
@@ -190,7 +314,8 @@ impl WaitState {
     fn new(
         rl: RaylibHandle,
         thread: RaylibThread,
-        channel: Channel<TcpStream>,
+        channel: Connection,
+        id: PlayerId,
         player: Player,
         map: Map,
         camera: Camera3D,
@@ -199,36 +324,93 @@ impl WaitState {
             rl,
             thread,
             channel,
+            id,
+            rules: Rules::default(),
             player,
+            enemies: Enemies::default(),
+            time: None,
             map,
+            map_error: None,
             camera,
         }
     }
 }
 
+impl PacketHandler for WaitState {
+    fn channel(&mut self) -> &mut dyn Transport {
+        self.channel.as_mut()
+    }
+
+    fn on_player(
+        &mut self,
+        id: PlayerId,
+        pos: ChannelVector3,
+        target: ChannelVector2,
+        time: f64,
+        weapon: WeaponKind,
+    ) {
+        // Our own position only comes again when the map changed
+        if id == self.id {
+            self.player.set_stats(pos, target);
+        } else {
+            let max_health = self.rules.starting_health;
+            self.enemies
+                .update(id, pos, target, time, weapon, max_health);
+        }
+    }
+
+    fn on_ammo(&mut self, weapon: WeaponKind, magazine: u8, reserve: u16) {
+        self.player.set_ammo(weapon, magazine, reserve);
+    }
+
+    fn on_left(&mut self, id: PlayerId) {
+        self.enemies.remove(&id);
+    }
+
+    // The rotation moved on before the match started
+    fn on_map(&mut self, map: MapInfo) {
+        match build_map(&map) {
+            Ok(map) => self.map = map,
+            Err(message) => self.map_error = Some(message),
+        }
+    }
+
+    // Sent before anything else in the match
+    fn on_rules(&mut self, rules: Rules) {
+        self.player.set_max_health(rules.starting_health);
+        self.rules = rules;
+    }
+
+    // The countdown starts once the server has sent every enemy position
+    fn on_time(&mut self, time: u8) {
+        self.time = Some(time);
+    }
+}
+
 impl GameState for WaitState {
     fn run(mut self: Box<Self>) -> Option<Box<dyn GameState>> {
-        let text = "Waiting for enemy...";
+        let text = "Waiting for enemies...";
         let text_width = self.rl.measure_text(text, 50);
         let text_x = Game::SCREEN_WIDTH / 2 - text_width / 2;
 
-        self.channel
-            .stream
-            .set_nonblocking(true)
-            .expect("Set non-blocking failed");
-
-        self.channel
-            .stream
-            .set_nodelay(true)
-            .expect("Set no-delay failed");
-
-        let mut enemy = Player::default();
+        if let Err(e) = self.channel.set_nonblocking(true) {
+            return Some(LobbyState::disconnected(self.rl, self.thread, e));
+        }
 
         loop {
             if self.rl.window_should_close() {
                 break None;
             }
 
+            if let Err(e) = self.dispatch_pending() {
+                break Some(LobbyState::disconnected(self.rl, self.thread, e));
+            }
+            if let Some(message) = self.map_error.take() {
+                let mut lobby = LobbyState::new(self.rl, self.thread);
+                lobby.message = message;
+                break Some(Box::new(lobby));
+            }
+
             // Update
             self.rl
                 .update_camera(&mut self.camera, CameraMode::CAMERA_ORBITAL);
@@ -241,14 +423,17 @@ impl GameState for WaitState {
             d.draw_text(text, text_x, 100, 50, Color::BLACK);
             drop(d);
 
-            // Check if enemy position sent
-            if let Ok(_) = enemy.read_stats(&mut self.channel) {
+            // Check if the match started
+            if let Some(time) = self.time {
                 break Some(Box::new(CountDownState::new(
                     self.rl,
                     self.thread,
                     self.channel,
+                    self.id,
+                    self.rules,
                     self.player,
-                    enemy,
+                    self.enemies,
+                    time,
                     self.map,
                     self.camera,
                 )));
@@ -260,20 +445,27 @@ impl GameState for WaitState {
 struct CountDownState {
     rl: RaylibHandle,
     thread: RaylibThread,
-    channel: Channel<TcpStream>,
+    channel: Connection,
+    id: PlayerId,
+    rules: Rules,
     player: Player,
-    enemy: Player,
+    enemies: Enemies,
+    time: u8,
     map: Map,
     camera: Camera3D,
 }
 
 impl CountDownState {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         rl: RaylibHandle,
         thread: RaylibThread,
-        channel: Channel<TcpStream>,
+        channel: Connection,
+        id: PlayerId,
+        rules: Rules,
         player: Player,
-        enemy: Player,
+        enemies: Enemies,
+        time: u8,
         map: Map,
         camera: Camera3D,
     ) -> Self {
@@ -281,36 +473,71 @@ impl CountDownState {
             rl,
             thread,
             channel,
+            id,
+            rules,
             player,
-            enemy,
+            enemies,
+            time,
             map,
             camera,
         }
     }
 }
 
+impl PacketHandler for CountDownState {
+    fn channel(&mut self) -> &mut dyn Transport {
+        self.channel.as_mut()
+    }
+
+    fn on_player(
+        &mut self,
+        id: PlayerId,
+        pos: ChannelVector3,
+        target: ChannelVector2,
+        time: f64,
+        weapon: WeaponKind,
+    ) {
+        if id != self.id {
+            let max_health = self.rules.starting_health;
+            self.enemies
+                .update(id, pos, target, time, weapon, max_health);
+        }
+    }
+
+    fn on_ammo(&mut self, weapon: WeaponKind, magazine: u8, reserve: u16) {
+        self.player.set_ammo(weapon, magazine, reserve);
+    }
+
+    fn on_left(&mut self, id: PlayerId) {
+        self.enemies.remove(&id);
+    }
+
+    fn on_time(&mut self, time: u8) {
+        self.time = time;
+    }
+}
+
 impl GameState for CountDownState {
     fn run(mut self: Box<Self>) -> Option<Box<dyn GameState>> {
-        let mut text = String::from("3");
-        let text_width = self.rl.measure_text(&text, 50);
-        let text_x = Game::SCREEN_WIDTH / 2 - text_width / 2;
         loop {
             if self.rl.window_should_close() {
                 break None;
             }
 
             // Receive time from server
-            if let Ok(Packet::Time(time)) = self.channel.receive() {
-                text = time.to_string();
+            if let Err(e) = self.dispatch_pending() {
+                break Some(LobbyState::disconnected(self.rl, self.thread, e));
             }
 
-            if &text == "0" {
+            if self.time == 0 {
                 break Some(Box::new(PlayState::new(
                     self.rl,
                     self.thread,
                     self.channel,
+                    self.id,
+                    self.rules,
                     self.player,
-                    self.enemy,
+                    self.enemies,
                     self.map,
                 )));
             }
@@ -318,13 +545,19 @@ impl GameState for CountDownState {
             // Update
             self.rl
                 .update_camera(&mut self.camera, CameraMode::CAMERA_ORBITAL);
+            self.enemies.interpolate(interpolation_delay(&self.rules));
 
             // Draw
             let mut d = self.rl.begin_drawing(&self.thread);
             d.clear_background(Color::SKYBLUE);
             self.map.draw(&mut d, &self.camera);
             self.player.draw(&mut d, &self.camera);
-            self.enemy.draw(&mut d, &self.camera);
+            for enemy in self.enemies.values() {
+                enemy.draw(&mut d, &self.camera);
+            }
+            let text = self.time.to_string();
+            let text_width = d.measure_text(&text, 50);
+            let text_x = Game::SCREEN_WIDTH / 2 - text_width / 2;
             d.draw_text(&text, text_x, 100, 50, Color::BLACK);
         }
     }
@@ -333,29 +566,137 @@ impl GameState for CountDownState {
 struct PlayState {
     rl: RaylibHandle,
     thread: RaylibThread,
-    channel: Channel<TcpStream>,
+    channel: Connection,
+    id: PlayerId,
+    rules: Rules,
     player: Player,
-    enemy: Player,
+    enemies: Enemies,
     map: Map,
+    hurt: bool,
+    winner: Option<Winner>,
+    // Usually arrives together with the result
+    vote: Option<Vote>,
 }
 
 impl PlayState {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         rl: RaylibHandle,
         thread: RaylibThread,
-        channel: Channel<TcpStream>,
+        channel: Connection,
+        id: PlayerId,
+        rules: Rules,
         player: Player,
-        enemy: Player,
+        enemies: Enemies,
         map: Map,
     ) -> Self {
         PlayState {
             rl,
             thread,
             channel,
+            id,
+            rules,
             player,
-            enemy,
+            enemies,
             map,
+            hurt: false,
+            winner: None,
+            vote: None,
+        }
+    }
+
+    // Tell the server what the player did this frame, it decides what comes
+    // of it
+    // The first ray is along the aim
+    fn send_actions(&mut self, inputs: Vec<Input>, rays: &[Ray]) -> Result<(), ChannelError> {
+        if !inputs.is_empty() {
+            let inputs = self.player.unacknowledged_inputs();
+            self.channel.send_unreliable(Packet::Inputs(inputs))?;
+        }
+
+        // Handle shooting, the server decides whether the shot hits
+        if let Some(aim) = rays.first() {
+            self.channel.send(Packet::Fire {
+                after: self.player.last_input(),
+                aim: ChannelVector3::from(aim.direction),
+                time: self.enemies.newest_snapshot(),
+            })?;
+        }
+
+        // Reloading takes as long on the server, it only has to know when it started
+        if self.rl.is_key_pressed(KeyboardKey::KEY_R) && self.player.reload() {
+            self.channel.send(Packet::Reload)?;
+        }
+
+        // Number keys pick a weapon, in the order of WeaponKind::ALL
+        let keys = [
+            KeyboardKey::KEY_ONE,
+            KeyboardKey::KEY_TWO,
+            KeyboardKey::KEY_THREE,
+        ];
+        for (key, weapon) in keys.into_iter().zip(WeaponKind::ALL) {
+            if self.rl.is_key_pressed(key) && self.player.equip(weapon) {
+                self.channel.send(Packet::Equip(weapon))?;
+            }
         }
+        Ok(())
+    }
+
+    // Enemies that left the match no longer count
+    fn alive_enemies(&self) -> usize {
+        self.enemies
+            .values()
+            .filter(|enemy| enemy.get_health() > 0)
+            .count()
+    }
+}
+
+impl PacketHandler for PlayState {
+    fn channel(&mut self) -> &mut dyn Transport {
+        self.channel.as_mut()
+    }
+
+    fn on_player(
+        &mut self,
+        id: PlayerId,
+        pos: ChannelVector3,
+        target: ChannelVector2,
+        time: f64,
+        weapon: WeaponKind,
+    ) {
+        if id != self.id {
+            let max_health = self.rules.starting_health;
+            self.enemies
+                .update(id, pos, target, time, weapon, max_health);
+        }
+    }
+
+    fn on_position(&mut self, sequence: u32, state: MoveState) {
+        self.player.reconcile(sequence, state, &self.map.obstacles);
+    }
+
+    fn on_health(&mut self, id: PlayerId, health: u8) {
+        if id == self.id {
+            self.hurt |= self.player.set_health(health);
+        } else if let Some(enemy) = self.enemies.get_mut(&id) {
+            enemy.set_health(health);
+        }
+    }
+
+    fn on_ammo(&mut self, weapon: WeaponKind, magazine: u8, reserve: u16) {
+        self.player.set_ammo(weapon, magazine, reserve);
+    }
+
+    fn on_left(&mut self, id: PlayerId) {
+        self.enemies.remove(&id);
+    }
+
+    fn on_game_over(&mut self, winner: Winner) {
+        self.winner = Some(winner);
+    }
+
+    fn on_map_vote(&mut self, maps: Vec<String>, seconds: u8) {
+        self.vote = Some(Vote::new(maps, seconds));
     }
 }
 
@@ -365,7 +706,7 @@ impl GameState for PlayState {
         let audio = RaylibAudio::init_audio_device().expect("Enabling audio failed");
 
         self.rl.disable_cursor();
-        let mut ray: Option<Ray> = None;
+        let mut rays: Vec<Ray> = Vec::new();
 
         // Load crosshair texture
         let image = Image::load_image("./resources/crosshair003.png").expect("Load image failed");
@@ -400,45 +741,59 @@ impl GameState for PlayState {
                 self.rl.disable_cursor();
             }
 
-            // Receive enemy position
-            let _ = self.enemy.read_stats(&mut self.channel);
-
-            // Receive player health
-            let _ = self.player.read_health(&mut self.channel, &fx_ouch_sound);
-
-            // Update player
-            self.player.update(&self.rl, &self.map.objects, &mut ray);
+            // Receive enemy positions and health of every player. The server
+            // closes the connection soon after the result, so check it first.
+            let received = self.dispatch_pending();
+            if let Some(winner) = self.winner.take() {
+                self.rl.enable_cursor();
+                break Some(Box::new(ResultsState::new(
+                    self.rl,
+                    self.thread,
+                    self.channel,
+                    self.id,
+                    winner,
+                    self.map,
+                    self.vote,
+                )));
+            }
+            if let Err(e) = received {
+                self.rl.enable_cursor();
+                break Some(LobbyState::disconnected(self.rl, self.thread, e));
+            }
+            if self.hurt {
+                fx_ouch_sound.play();
+                self.hurt = false;
+            }
 
-            // Handle shooting
-            if let Some(r) = ray {
+            // Update player, the server moves it the same way once the input arrives
+            let inputs = self.player.update(&self.rl, &self.map.obstacles, &mut rays);
+            if !rays.is_empty() {
                 fx_gun_sound.play();
-                let collision = self.enemy.collision(r);
-                if collision.hit {
-                    self.enemy.decrease_health();
-                }
-                ray = None;
+            }
+            let sent = self.send_actions(inputs, &rays);
+            rays.clear();
+            if let Err(e) = sent {
+                self.rl.enable_cursor();
+                break Some(LobbyState::disconnected(self.rl, self.thread, e));
             }
 
-            // Send next player position
-            self.player
-                .write_stats(&mut self.channel)
-                .expect("Send player position failed");
-
-            // Send enemy health
-            self.enemy
-                .write_health(&mut self.channel)
-                .expect("Send enemy health failed");
+            // Remote players are drawn slightly in the past
+            self.enemies.interpolate(interpolation_delay(&self.rules));
 
             // Draw
+            let alive_enemies = self.alive_enemies();
             let player_camera = self.player.get_camera();
             let mut d = self.rl.begin_drawing(&self.thread);
             d.clear_background(Color::SKYBLUE);
             self.player.draw_gun(&mut d, player_camera);
-            self.enemy.draw(&mut d, player_camera);
+            for enemy in self.enemies.values() {
+                enemy.draw(&mut d, player_camera);
+            }
             self.map.draw(&mut d, player_camera);
 
             // Draw health bar
             self.player.draw_health_bar(&mut d);
+            self.player.draw_ammo(&mut d);
 
             // Draw Crosshair
             d.draw_texture(
@@ -448,38 +803,242 @@ impl GameState for PlayState {
                 Color::WHITE,
             );
 
-            // Player and enemy position debugging
+            // Player position debugging
             let (x, z) = self.player.get_pos();
-            let (x2, z2) = self.enemy.get_pos();
             d.draw_text(&format!("Self x: {}", x), 10, 10, 20, Color::RED);
             d.draw_text(&format!("Self z: {}", z), 10, 30, 20, Color::RED);
-            d.draw_text(&format!("Enemy x: {}", x2), 10, 50, 20, Color::RED);
-            d.draw_text(&format!("Enemy z: {}", z2), 10, 70, 20, Color::RED);
-            drop(d);
+            d.draw_text(
+                &format!("Enemies alive: {}", alive_enemies),
+                10,
+                50,
+                20,
+                Color::RED,
+            );
 
-            // Check if game over
+            // Dead players watch until the server ends the match
             if self.player.get_health() == 0 {
-                self.rl.enable_cursor();
-                self.channel
-                    .stream
-                    .shutdown(Shutdown::Both)
-                    .expect("Failed to shutdown TCP stream");
-                break Some(Box::new(LobbyState::new(
-                    self.rl,
-                    self.thread,
-                    Winner::Enemy,
-                )));
-            } else if self.enemy.get_health() == 0 {
-                self.rl.enable_cursor();
-                self.channel
-                    .stream
-                    .shutdown(Shutdown::Both)
-                    .expect("Failed to shutdown TCP stream");
-                break Some(Box::new(LobbyState::new(
-                    self.rl,
-                    self.thread,
-                    Winner::Player,
-                )));
+                let text = "YOU DIED";
+                let text_width = d.measure_text(text, 60);
+                d.draw_text(
+                    text,
+                    Game::SCREEN_WIDTH / 2 - text_width / 2,
+                    100,
+                    60,
+                    Color::BLACK,
+                );
+            }
+        }
+    }
+}
+
+// The vote on the next map, held on the results screen
+struct Vote {
+    maps: Vec<String>,
+    votes: Vec<u8>,
+    closes: Instant,
+    choice: Option<u8>,
+    next: Option<String>,
+}
+
+impl Vote {
+    fn new(maps: Vec<String>, seconds: u8) -> Self {
+        Vote {
+            votes: vec![0; maps.len()],
+            maps,
+            closes: Instant::now() + Duration::from_secs(seconds as u64),
+            choice: None,
+            next: None,
+        }
+    }
+
+    fn seconds_left(&self) -> u64 {
+        self.closes
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+            .ceil() as u64
+    }
+}
+
+struct ResultsState {
+    rl: RaylibHandle,
+    thread: RaylibThread,
+    // Only kept open for the vote, the server closes it afterwards
+    channel: Connection,
+    connected: bool,
+    lobby_button: Button,
+    map: Map,
+    camera: Camera3D,
+    title: &'static str,
+    detail: String,
+    vote: Option<Vote>,
+}
+
+impl ResultsState {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        rl: RaylibHandle,
+        thread: RaylibThread,
+        channel: Connection,
+        id: PlayerId,
+        winner: Winner,
+        map: Map,
+        vote: Option<Vote>,
+    ) -> Self {
+        let (title, detail) = match winner {
+            Winner::Player(winner) if winner == id => {
+                ("YOU WON", String::from("Last one standing"))
+            }
+            Winner::Forfeit(winner) if winner == id => {
+                ("YOU WON", String::from("Your opponents left the match"))
+            }
+            Winner::Player(winner) | Winner::Forfeit(winner) => {
+                ("YOU LOSE", format!("Player {} won the match", winner))
+            }
+            Winner::None => ("DRAW", String::from("Nobody survived")),
+        };
+
+        let x = Game::SCREEN_WIDTH / 2 - Button::WIDTH / 2;
+        let y = Game::SCREEN_HEIGHT / 2 - Button::HEIGHT / 2;
+
+        ResultsState {
+            rl,
+            thread,
+            channel,
+            connected: true,
+            lobby_button: Button::new(
+                Rectangle {
+                    x: x as f32,
+                    y: y as f32,
+                    width: Button::WIDTH as f32,
+                    height: Button::HEIGHT as f32,
+                },
+                String::from("LOBBY"),
+            ),
+            map,
+            camera: overview_camera(),
+            title,
+            detail,
+            vote,
+        }
+    }
+
+    // Number keys vote for the map listed under them, the server counts the
+    // last vote sent
+    fn update_vote(&mut self) {
+        let Some(vote) = &mut self.vote else {
+            return;
+        };
+        if vote.next.is_some() || !self.connected {
+            return;
+        }
+
+        let keys = [
+            KeyboardKey::KEY_ONE,
+            KeyboardKey::KEY_TWO,
+            KeyboardKey::KEY_THREE,
+            KeyboardKey::KEY_FOUR,
+            KeyboardKey::KEY_FIVE,
+            KeyboardKey::KEY_SIX,
+            KeyboardKey::KEY_SEVEN,
+            KeyboardKey::KEY_EIGHT,
+            KeyboardKey::KEY_NINE,
+        ];
+        for (choice, key) in keys.into_iter().enumerate().take(vote.maps.len()) {
+            if self.rl.is_key_pressed(key) && vote.choice != Some(choice as u8) {
+                vote.choice = Some(choice as u8);
+                self.connected = self.channel.send(Packet::Vote(choice as u8)).is_ok();
+            }
+        }
+    }
+
+    fn draw_vote(vote: &Vote, d: &mut RaylibDrawHandle, y: i32) {
+        let x = Game::SCREEN_WIDTH / 2 - Button::WIDTH / 2;
+        let line_height = Game::FONT_SIZE + 10;
+
+        let heading = match &vote.next {
+            Some(next) => format!("NEXT MAP: {}", next),
+            None => format!("VOTE FOR THE NEXT MAP ({}s)", vote.seconds_left()),
+        };
+        d.draw_text(&heading, x, y, Game::FONT_SIZE, Color::BLACK);
+
+        for (i, (name, votes)) in vote.maps.iter().zip(&vote.votes).enumerate() {
+            let color = if vote.choice == Some(i as u8) {
+                Color::MAROON
+            } else {
+                Color::BLACK
+            };
+            d.draw_text(
+                &format!("{}  {}  {} votes", i + 1, name, votes),
+                x,
+                y + line_height * (i as i32 + 1),
+                Game::FONT_SIZE,
+                color,
+            );
+        }
+    }
+}
+
+impl PacketHandler for ResultsState {
+    fn channel(&mut self) -> &mut dyn Transport {
+        self.channel.as_mut()
+    }
+
+    // Only when the result came on its own
+    fn on_map_vote(&mut self, maps: Vec<String>, seconds: u8) {
+        self.vote = Some(Vote::new(maps, seconds));
+    }
+
+    fn on_votes(&mut self, votes: Vec<u8>) {
+        if let Some(vote) = &mut self.vote {
+            vote.votes = votes;
+        }
+    }
+
+    fn on_next_map(&mut self, name: String) {
+        if let Some(vote) = &mut self.vote {
+            vote.next = Some(name);
+        }
+    }
+}
+
+impl GameState for ResultsState {
+    fn run(mut self: Box<Self>) -> Option<Box<dyn GameState>> {
+        let title_width = self.rl.measure_text(self.title, 60);
+        let title_x = Game::SCREEN_WIDTH / 2 - title_width / 2;
+        let detail_width = self.rl.measure_text(&self.detail, Game::FONT_SIZE);
+        let detail_x = Game::SCREEN_WIDTH / 2 - detail_width / 2;
+        let vote_y = Game::SCREEN_HEIGHT / 2 + Button::HEIGHT / 2 + Button::SPACING;
+
+        loop {
+            if self.rl.window_should_close() {
+                break None;
+            }
+
+            // Votes keep coming in until the server closes the connection
+            if self.connected {
+                self.connected = self.dispatch_pending().is_ok() && self.channel.flush().is_ok();
+            }
+
+            // Update
+            self.rl
+                .update_camera(&mut self.camera, CameraMode::CAMERA_ORBITAL);
+            self.lobby_button.update(&self.rl);
+            self.update_vote();
+
+            // Draw
+            let mut d = self.rl.begin_drawing(&self.thread);
+            d.clear_background(Color::SKYBLUE);
+            self.map.draw(&mut d, &self.camera);
+            d.draw_text(self.title, title_x, 100, 60, Color::BLACK);
+            d.draw_text(&self.detail, detail_x, 180, Game::FONT_SIZE, Color::BLACK);
+            self.lobby_button.draw(&mut d);
+            if let Some(vote) = &self.vote {
+                Self::draw_vote(vote, &mut d, vote_y);
+            }
+            drop(d);
+
+            if self.lobby_button.is_clicked() {
+                break Some(Box::new(LobbyState::new(self.rl, self.thread)));
             }
         }
     }
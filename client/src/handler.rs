@@ -0,0 +1,82 @@
+use game_channel::error::ChannelError;
+use game_channel::{
+    ChannelVector2, ChannelVector3, MapInfo, Packet, PlayerId, Rules, Transport, Winner,
+};
+use game_core::movement::MoveState;
+use game_core::weapon::WeaponKind;
+use std::io::ErrorKind;
+
+// ----------------------------------------------------------------------------
+// Packet dispatch
+// Every packet that arrives is routed to the handler for its variant, so a
+// state never loses a packet just because it was expecting a different one.
+// ----------------------------------------------------------------------------
+
+pub trait PacketHandler {
+    fn channel(&mut self) -> &mut dyn Transport;
+
+    fn on_rules(&mut self, _rules: Rules) {}
+    fn on_player(
+        &mut self,
+        _id: PlayerId,
+        _pos: ChannelVector3,
+        _target: ChannelVector2,
+        _time: f64,
+        _weapon: WeaponKind,
+    ) {
+    }
+    fn on_position(&mut self, _sequence: u32, _state: MoveState) {}
+    fn on_health(&mut self, _id: PlayerId, _health: u8) {}
+    fn on_ammo(&mut self, _weapon: WeaponKind, _magazine: u8, _reserve: u16) {}
+    fn on_left(&mut self, _id: PlayerId) {}
+    fn on_time(&mut self, _time: u8) {}
+    fn on_game_over(&mut self, _winner: Winner) {}
+    fn on_map_vote(&mut self, _maps: Vec<String>, _seconds: u8) {}
+    fn on_votes(&mut self, _votes: Vec<u8>) {}
+    fn on_next_map(&mut self, _name: String) {}
+    fn on_map(&mut self, _map: MapInfo) {}
+
+    fn dispatch(&mut self, packet: Packet) {
+        match packet {
+            Packet::Rules(rules) => self.on_rules(rules),
+            Packet::Player {
+                id,
+                pos,
+                target,
+                time,
+                weapon,
+            } => self.on_player(id, pos, target, time, weapon),
+            Packet::Position { sequence, state } => self.on_position(sequence, state),
+            Packet::Health { id, health } => self.on_health(id, health),
+            Packet::Ammo {
+                weapon,
+                magazine,
+                reserve,
+            } => self.on_ammo(weapon, magazine, reserve),
+            Packet::Left(id) => self.on_left(id),
+            Packet::Time(time) => self.on_time(time),
+            Packet::GameOver(winner) => self.on_game_over(winner),
+            Packet::MapVote { maps, seconds } => self.on_map_vote(maps, seconds),
+            Packet::Votes(votes) => self.on_votes(votes),
+            Packet::NextMap(name) => self.on_next_map(name),
+            Packet::Map(map) => self.on_map(map),
+            // Only ever sent by clients
+            Packet::Inputs(_)
+            | Packet::Fire { .. }
+            | Packet::Reload
+            | Packet::Equip(_)
+            | Packet::Vote(_) => (),
+        }
+    }
+
+    // Handle everything received so far, stopping once the stream would block
+    fn dispatch_pending(&mut self) -> Result<(), ChannelError> {
+        loop {
+            match self.channel().receive() {
+                Ok(packet) => self.dispatch(packet),
+                Err(ChannelError::Io(e)) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -13,14 +13,23 @@ use raylib::RaylibHandle;
 pub struct InputBox {
     rect: Rectangle,
     text: String,
+    placeholder: String,
 }
 
 impl InputBox {
-    const WIDTH: i32 = 400;
-    const HEIGHT: i32 = 50;
+    pub const WIDTH: i32 = 400;
+    pub const HEIGHT: i32 = 50;
     const MAX_INPUT_CHAR: usize = 20;
     const FONT_SIZE: i32 = 40;
 
+    pub fn new(rect: Rectangle, placeholder: String) -> Self {
+        InputBox {
+            rect,
+            text: String::new(),
+            placeholder,
+        }
+    }
+
     pub fn update(&mut self, rl: &mut RaylibHandle) {
         if self.rect.check_collision_point_rec(rl.get_mouse_position()) {
             rl.set_mouse_cursor(raylib::ffi::MouseCursor::MOUSE_CURSOR_IBEAM);
@@ -59,6 +68,16 @@ impl InputBox {
             color,
         );
 
+        if self.text.is_empty() && !mouse_on_text {
+            d.draw_text(
+                &self.placeholder,
+                self.rect.x as i32 + 5,
+                self.rect.y as i32 + 8,
+                Self::FONT_SIZE,
+                Color::LIGHTGRAY,
+            );
+        }
+
         d.draw_text(
             &self.text,
             self.rect.x as i32 + 5,
@@ -87,14 +106,14 @@ impl Default for InputBox {
     fn default() -> Self {
         let x = Game::SCREEN_WIDTH / 2 - InputBox::WIDTH / 2;
         let y = Game::SCREEN_HEIGHT / 2 - Button::HEIGHT / 2 - Button::SPACING - InputBox::HEIGHT;
-        InputBox {
-            rect: Rectangle {
+        InputBox::new(
+            Rectangle {
                 x: x as f32,
                 y: y as f32,
                 width: InputBox::WIDTH as f32,
                 height: InputBox::HEIGHT as f32,
             },
-            text: String::new(),
-        }
+            String::from("Server address"),
+        )
     }
 }
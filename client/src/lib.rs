@@ -1,7 +1,10 @@
 pub mod button;
+pub mod enemies;
 pub mod game;
 pub mod gun;
+pub mod handler;
 pub mod input_box;
 pub mod map;
 pub mod object;
 pub mod player;
+pub mod weapon;
@@ -1,80 +1,78 @@
+use game_core::error::MapError;
+use game_core::geometry::Aabb;
+use game_core::map::{Layout, PickupKind};
 use raylib::{
     color::Color,
     math::{Vector2, Vector3},
 };
-use std::fs::read_to_string;
+use std::path::Path;
 
 use crate::object::{Cuboid, Drawable3D, Plane};
 
 pub struct Map {
     plane: Plane,
-    pub objects: Vec<Cuboid>,
+    objects: Vec<Cuboid>,
+    // What the player collides with, the same boxes the server uses
+    pub obstacles: Vec<Aabb>,
 }
 
 impl Map {
-    const WIDTH: f32 = 40.0;
-    const LENGTH: f32 = 40.0;
-    const UNIT: f32 = 2.0;
-    const WALL_HEIGHT: f32 = Map::UNIT * 2.0;
-}
+    pub const DEFAULT_PATH: &str = "./resources/map.txt";
+
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        Layout::load(path).map(|layout| Map::new(&layout))
+    }
 
-impl Default for Map {
-    fn default() -> Self {
+    pub fn new(layout: &Layout) -> Self {
         // Construct plane
-        let plane = Plane::new(
-            Vector3::zero(),
-            Vector2::new(Map::WIDTH, Map::LENGTH),
-            Color::GRAY,
-        );
+        let size = layout.size();
+        let plane = Plane::new(Vector3::zero(), Vector2::new(size.x, size.z), Color::GRAY);
 
         let mut objects: Vec<Cuboid> = Vec::new();
+        let mut obstacles: Vec<Aabb> = Vec::new();
 
         // Construct walls
-        let wall_offset = Map::LENGTH / 2.0 + Map::UNIT / 2.0;
-        objects.push(Cuboid::new(
-            Vector3::new(wall_offset, Map::UNIT, 0.0),
-            Vector3::new(Map::UNIT, Map::WALL_HEIGHT, Map::WIDTH),
-            Color::DARKGRAY,
-        ));
-        objects.push(Cuboid::new(
-            Vector3::new(-wall_offset, Map::UNIT, 0.0),
-            Vector3::new(Map::UNIT, Map::WALL_HEIGHT, Map::WIDTH),
-            Color::DARKGRAY,
-        ));
-        objects.push(Cuboid::new(
-            Vector3::new(0.0, Map::UNIT, wall_offset),
-            Vector3::new(Map::LENGTH, Map::WALL_HEIGHT, Map::UNIT),
-            Color::DARKGRAY,
-        ));
-        objects.push(Cuboid::new(
-            Vector3::new(0.0, Map::UNIT, -wall_offset),
-            Vector3::new(Map::LENGTH, Map::WALL_HEIGHT, Map::UNIT),
-            Color::DARKGRAY,
-        ));
+        for wall in layout.walls() {
+            obstacles.push(wall);
+            objects.push(Cuboid::new(
+                wall.center().into(),
+                wall.size().into(),
+                Color::DARKGRAY,
+            ));
+        }
 
         // Construct cuboids
-        let maps = read_to_string("./resources/map.txt").unwrap();
-        let maps: Vec<Vec<char>> = maps.lines().map(|line| line.chars().collect()).collect();
-
-        let half = Map::WIDTH / 2.0;
+        for block in &layout.blocks {
+            let [r, g, b] = block.color;
+            obstacles.push(block.aabb);
+            objects.push(Cuboid::new(
+                block.aabb.center().into(),
+                block.aabb.size().into(),
+                Color::new(r, g, b, 255),
+            ));
+        }
 
-        for i in 0..maps.len() {
-            for j in 0..maps[i].len() {
-                let cube_height = maps[i][j].to_digit(10).unwrap();
-                if cube_height != 0 {
-                    let size = Vector3::new(Map::UNIT, Map::UNIT * cube_height as f32, Map::UNIT);
-                    let pos = Vector3::new(
-                        j as f32 * Map::UNIT - half + Map::UNIT / 2.0,
-                        size.y / 2.0,
-                        i as f32 * Map::UNIT - half + Map::UNIT / 2.0,
-                    );
-                    objects.push(Cuboid::new(pos, size, Color::RED));
-                }
-            }
+        // Pickups are only markers for now
+        for pickup in &layout.pickups {
+            let color = match pickup.kind {
+                PickupKind::Health => Color::LIME,
+                PickupKind::Ammo => Color::GOLD,
+            };
+            objects.push(Cuboid::new(
+                Vector3::new(pickup.pos.x, Self::PICKUP_SIZE, pickup.pos.z),
+                Vector3::one() * Self::PICKUP_SIZE,
+                color,
+            ));
         }
 
-        Map { plane, objects }
+        Map {
+            plane,
+            objects,
+            obstacles,
+        }
     }
+
+    const PICKUP_SIZE: f32 = 0.6;
 }
 
 impl Drawable3D for Map {
@@ -1,23 +1,37 @@
 use crate::game::Game;
-use crate::gun::Pistol;
 use crate::object::{Cuboid, Drawable3D, Movable};
-use game_channel::{Channel, ChannelVector2, Packet};
-use raylib::audio::Sound;
+use crate::weapon::{self, Weapon};
+use game_core::geometry::{self, Aabb};
+use game_core::movement::{self, Input, MoveState, Timestep};
+use game_core::weapon::WeaponKind;
+use game_core::{ChannelVector2, ChannelVector3};
 use raylib::camera::Camera3D;
 use raylib::color::Color;
 use raylib::ffi::{KeyboardKey, MouseButton};
-use raylib::math::{BoundingBox, RayCollision};
 use raylib::math::{Quaternion, Ray, Vector2, Vector3};
 use raylib::prelude::RaylibDraw;
 use raylib::RaylibHandle;
-use std::net::TcpStream;
+use std::collections::VecDeque;
+
+// Repeated in every packet of inputs, so losing this many packets in a row
+// costs nothing
+const RESENT_INPUTS: usize = 12;
 
 pub struct Player {
     camera: Camera3D,
-    velocity: Vector3,
+    movement: MoveState,
+    // Where the last step started, drawn positions blend from here
+    previous_pos: ChannelVector3,
+    timestep: Timestep,
+    // Inputs already applied locally that the server has not acknowledged yet
+    pending_inputs: VecDeque<Input>,
+    next_input: u32,
     body: Cuboid,
-    pistol: Pistol,
+    // One of each kind, by WeaponKind::index
+    weapons: Vec<Box<dyn Weapon>>,
+    equipped: WeaponKind,
     health: u8,
+    max_health: u8,
 }
 
 impl Default for Player {
@@ -27,37 +41,55 @@ impl Default for Player {
             Camera3D::perspective(camera_pos, Vector3::forward(), Vector3::up(), Self::FOV);
         let body_pos = Vector3::new(0.0, Self::PLAYER_HEIGHT_HALF, 0.0);
         let body_size = Vector3::new(
-            Player::PLAYER_UNIT,
-            Player::PLAYER_HEIGHT,
-            Player::PLAYER_UNIT,
+            geometry::PLAYER_UNIT,
+            geometry::PLAYER_HEIGHT,
+            geometry::PLAYER_UNIT,
         );
         let body = Cuboid::new(body_pos, body_size, Color::GREEN);
-        let pistol = Pistol::new();
 
         Player {
             camera,
-            velocity: Vector3::zero(),
+            movement: MoveState::at(ChannelVector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }),
+            previous_pos: ChannelVector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            timestep: Timestep::default(),
+            pending_inputs: VecDeque::new(),
+            // 0 means no input was applied yet
+            next_input: 1,
             body,
-            pistol,
+            weapons: weapon::arsenal(),
+            equipped: WeaponKind::Pistol,
             health: 100,
+            max_health: 100,
         }
     }
 }
 
 impl Player {
     const FOV: f32 = 60.0;
-    const CAMERA_HEIGHT: f32 = 3.2;
+    const CAMERA_HEIGHT: f32 = geometry::EYE_HEIGHT;
     const CAMERA_MOUSE_SENSITIVITY: f32 = 0.0015;
-    const SPEED: f32 = 90.0;
-    const PLAYER_HEIGHT: f32 = 3.5;
-    const PLAYER_HEIGHT_HALF: f32 = Self::PLAYER_HEIGHT / 2.0;
-    const PLAYER_UNIT: f32 = 1.0;
+    const PLAYER_HEIGHT_HALF: f32 = geometry::PLAYER_HEIGHT / 2.0;
 
     pub fn get_camera(&self) -> &Camera3D {
         &self.camera
     }
 
-    pub fn update(&mut self, rl: &RaylibHandle, objects: &Vec<Cuboid>, rays: &mut Option<Ray>) {
+    // Moves the player right away and returns the inputs for every step taken,
+    // which must be sent to the server so it can move the player the same way
+    pub fn update(
+        &mut self,
+        rl: &RaylibHandle,
+        obstacles: &[Aabb],
+        rays: &mut Vec<Ray>,
+    ) -> Vec<Input> {
         // --------------------------------------------------------------------
         // Player turning
         // Some Logic taken from https://github.com/raysan5/raylib/blob/master/src/rcamera.h
@@ -97,152 +129,137 @@ impl Player {
 
         // ----------------------------------------------------------------
         // Player Movement
-        // Inspiration from https://gist.github.com/jakubtomsu/9cae5298f86d2b9d2aed48641a1a3dbd
+        // Predicted with the same code the server runs
         // ----------------------------------------------------------------
 
-        let dt = rl.get_frame_time();
-
         // Remove y component from forward to restrict movement to the ground
         forward.y = 0.0;
         forward.normalize();
 
-        if rl.is_key_down(KeyboardKey::KEY_W) {
-            self.velocity += forward * dt * Self::SPEED;
-        }
-        if rl.is_key_down(KeyboardKey::KEY_S) {
-            self.velocity -= forward * dt * Self::SPEED;
+        // The keys held this frame count for every step it covers
+        let steps = self.timestep.advance(rl.get_frame_time());
+        let mut inputs = Vec::new();
+        for _ in 0..steps {
+            let input = Input {
+                sequence: self.next_input,
+                forward: key_axis(rl, KeyboardKey::KEY_W, KeyboardKey::KEY_S),
+                right: key_axis(rl, KeyboardKey::KEY_D, KeyboardKey::KEY_A),
+                jump: rl.is_key_down(KeyboardKey::KEY_SPACE),
+                facing: ChannelVector2::from(forward),
+            };
+            self.next_input = self.next_input.wrapping_add(1);
+
+            self.previous_pos = self.movement.pos;
+            movement::step(&mut self.movement, &input, obstacles);
+            self.pending_inputs.push_back(input);
+            inputs.push(input);
+
+            // Every weapon cools down, like on the server
+            self.weapons
+                .iter_mut()
+                .for_each(|weapon| weapon.update(movement::TIMESTEP));
         }
-        if rl.is_key_down(KeyboardKey::KEY_D) {
-            self.velocity += right * dt * Self::SPEED;
-        }
-        if rl.is_key_down(KeyboardKey::KEY_A) {
-            self.velocity -= right * dt * Self::SPEED;
-        }
-
-        // Damping
-        self.velocity *= 0.85;
-
-        // Displacement
-        let displacement = self.velocity * dt;
-
-        // Check collision
-        let new_displacement = self.displacement_after_collision(displacement, objects);
-
-        // Change camera position
-        self.camera.position += new_displacement;
-        self.camera.target += new_displacement;
-
-        // Move body to match camera
-        self.move_body();
+        self.follow_movement();
 
         // ----------------------------------------------------------------
         // Shooting
         // ----------------------------------------------------------------
-        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) && self.weapon_mut().fire() {
             // let mut offset =
-            //     forward_copy * Pistol::BARREL_Z_OFFSET + right * -Pistol::BARREL_X_OFFSET;
-            // offset.y = Pistol::BARREL_Y_OFFSET;
-            rays.replace(Ray {
+            //     forward_copy * weapon::HOLD_Z_OFFSET + right * -weapon::HOLD_X_OFFSET;
+            // offset.y = weapon::HOLD_Y_OFFSET;
+            let directions = self.weapon().pellet_directions(forward_copy);
+            rays.extend(directions.into_iter().map(|direction| Ray {
                 // position: self.camera.position + offset,
                 position: self.camera.position,
-                direction: forward_copy,
-            });
+                direction,
+            }));
         }
-    }
 
-    fn displacement_after_collision(
-        &self,
-        mut displacement: Vector3,
-        objects: &Vec<Cuboid>,
-    ) -> Vector3 {
-        // Check horizontal collision
-        let new_x_pos = Vector3::new(
-            self.camera.position.x + displacement.x,
-            self.camera.position.y,
-            self.camera.position.z,
-        );
+        inputs
+    }
 
-        for obj in objects {
-            if Self::get_bounding_box(new_x_pos).check_collision_boxes(obj.get_bounding_box()) {
-                displacement.x = 0.0;
-                break;
-            }
-        }
+    // The newest inputs the server has not acknowledged, oldest first
+    pub fn unacknowledged_inputs(&self) -> Vec<Input> {
+        let skip = self.pending_inputs.len().saturating_sub(RESENT_INPUTS);
+        self.pending_inputs.iter().skip(skip).copied().collect()
+    }
 
-        // Check vertical collision
-        let new_z_pos = Vector3::new(
-            self.camera.position.x,
-            self.camera.position.y,
-            self.camera.position.z + displacement.z,
-        );
+    // Sequence of the newest input, 0 before the first one
+    pub fn last_input(&self) -> u32 {
+        self.next_input.wrapping_sub(1)
+    }
 
-        for obj in objects {
-            if Self::get_bounding_box(new_z_pos).check_collision_boxes(obj.get_bounding_box()) {
-                displacement.z = 0.0;
+    // The server applied our inputs up to sequence and ended up at state.
+    // Start over from there and replay the inputs it has not seen yet.
+    pub fn reconcile(&mut self, sequence: u32, state: MoveState, obstacles: &[Aabb]) {
+        while let Some(input) = self.pending_inputs.front() {
+            if input.sequence.wrapping_sub(sequence) as i32 > 0 {
                 break;
             }
+            self.pending_inputs.pop_front();
         }
 
-        displacement
+        self.movement = state;
+        for input in &self.pending_inputs {
+            movement::step(&mut self.movement, input, obstacles);
+        }
+        self.follow_movement();
     }
 
-    fn get_bounding_box(pos: Vector3) -> BoundingBox {
-        let half = Player::PLAYER_UNIT / 2.0;
-        let min = Vector3::new(pos.x - half, 0.0, pos.z - half);
-        let max = Vector3::new(pos.x + half, Player::PLAYER_HEIGHT, pos.z + half);
-        BoundingBox::new(min, max)
+    // Move the camera and body to the simulated position, keeping the view
+    // direction. Frames fall between steps, so blend from the previous step.
+    fn follow_movement(&mut self) {
+        let look = self.camera.target - self.camera.position;
+        let pos = self
+            .previous_pos
+            .lerp(self.movement.pos, self.timestep.alpha());
+        self.camera.position.x = pos.x;
+        self.camera.position.y = pos.y + Self::CAMERA_HEIGHT;
+        self.camera.position.z = pos.z;
+        self.camera.target = self.camera.position + look;
+        self.move_body();
     }
 
-    pub fn write_stats(&self, channel: &mut Channel<TcpStream>) -> Result<(), ()> {
-        channel
-            .send(Packet::Player {
-                pos: ChannelVector2::from(self.camera.position),
-                target: ChannelVector2::from(self.camera.target),
-            })
-            .map_err(|_| ())
+    // The target is only horizontal, the view keeps its pitch
+    pub fn set_stats(&mut self, pos: ChannelVector3, target: ChannelVector2) {
+        let pitch = self.camera.target.y - self.camera.position.y;
+        self.camera.position.x = pos.x;
+        self.camera.position.y = pos.y + Self::CAMERA_HEIGHT;
+        self.camera.position.z = pos.z;
+        self.camera.target.x = target.x;
+        self.camera.target.y = self.camera.position.y + pitch;
+        self.camera.target.z = target.z;
+        self.movement = MoveState::at(pos);
+        self.previous_pos = pos;
+        self.move_body();
     }
 
-    pub fn read_stats(&mut self, channel: &mut Channel<TcpStream>) -> Result<(), ()> {
-        if let Ok(Packet::Player { pos, target }) = channel.receive() {
-            self.camera.position.x = pos.x;
-            self.camera.position.z = pos.z;
-            self.camera.target.x = target.x;
-            self.camera.target.z = target.z;
-            self.move_body();
-            Ok(())
-        } else {
-            Err(())
-        }
+    // Returns whether the player lost health
+    pub fn set_health(&mut self, health: u8) -> bool {
+        let hurt = health < self.health;
+        self.health = health;
+        hurt
     }
 
-    pub fn write_health(&self, channel: &mut Channel<TcpStream>) -> Result<(), ()> {
-        channel.send(Packet::Health(self.health)).map_err(|_| ())
+    pub fn get_health(&self) -> u8 {
+        self.health
     }
 
-    pub fn read_health(
-        &mut self,
-        channel: &mut Channel<TcpStream>,
-        ouch: &Sound,
-    ) -> Result<(), ()> {
-        if let Ok(Packet::Health(health)) = channel.receive() {
-            if health < self.health {
-                ouch.play();
-            }
-            self.health = health;
-            Ok(())
-        } else {
-            Err(())
-        }
+    // Start at full health, as decided by the match rules
+    pub fn set_max_health(&mut self, health: u8) {
+        self.health = health;
+        self.max_health = health;
     }
 
-    pub fn get_health(&self) -> u8 {
-        self.health
+    fn health_ratio(&self) -> f32 {
+        self.health as f32 / self.max_health.max(1) as f32
     }
 
     fn move_body(&mut self) {
         self.body.move_to(Vector3::new(
             self.camera.position.x,
-            Self::PLAYER_HEIGHT_HALF,
+            self.camera.position.y - Self::CAMERA_HEIGHT + Self::PLAYER_HEIGHT_HALF,
             self.camera.position.z,
         ));
     }
@@ -264,13 +281,67 @@ impl Player {
         if cross_product.y < 0.0 {
             angle = 360.0 - angle;
         }
-        self.pistol
+        self.weapon()
             .draw_target(d, camera, self.camera.position, angle);
     }
 
+    fn weapon(&self) -> &dyn Weapon {
+        self.weapons[self.equipped.index()].as_ref()
+    }
+
+    fn weapon_mut(&mut self) -> &mut dyn Weapon {
+        self.weapons[self.equipped.index()].as_mut()
+    }
+
+    // Returns whether the weapon changed, the server has to be told about it
+    pub fn equip(&mut self, kind: WeaponKind) -> bool {
+        if kind == self.equipped {
+            return false;
+        }
+        self.weapon_mut().put_away();
+        self.equipped = kind;
+        true
+    }
+
+    // Returns whether a reload started, the server has to be told about it
+    pub fn reload(&mut self) -> bool {
+        self.weapon_mut().reload()
+    }
+
+    pub fn set_ammo(&mut self, kind: WeaponKind, magazine: u8, reserve: u16) {
+        self.weapons[kind.index()].set_ammo(magazine, reserve);
+    }
+
+    pub fn draw_ammo(&self, d: &mut raylib::prelude::RaylibDrawHandle) {
+        let weapon = self.weapon();
+        let ammo = weapon.ammo();
+        let text = match ammo.reload_progress() {
+            Some(_) => format!("{} RELOADING", weapon.kind().name()),
+            None => format!(
+                "{} {} / {}",
+                weapon.kind().name(),
+                ammo.magazine,
+                ammo.reserve
+            ),
+        };
+        let color = match ammo.magazine {
+            0 => Color::RED,
+            _ => Color::BLACK,
+        };
+        let font_size = 40;
+        let text_width = d.measure_text(&text, font_size);
+        d.draw_text(
+            &text,
+            Game::SCREEN_WIDTH - 20 - text_width,
+            Game::SCREEN_HEIGHT - 20 - font_size,
+            font_size,
+            color,
+        );
+    }
+
     pub fn draw_health_bar(&self, d: &mut raylib::prelude::RaylibDrawHandle) {
         let rect_size = Vector2::new(250.0, 50.0);
-        let health_size = Vector2::new(250.0 * self.health as f32 / 100.0, 50.0);
+        let health_size = Vector2::new(250.0 * self.health_ratio(), 50.0);
         let health_pos = Vector2::new(20.0, Game::SCREEN_HEIGHT as f32 - 20.0 - 50.0);
         d.draw_rectangle_v(health_pos, rect_size, Color::WHITE);
         d.draw_rectangle_v(health_pos, health_size, Color::LIGHTGREEN);
@@ -282,14 +353,11 @@ impl Player {
             Color::BLACK,
         );
     }
+}
 
-    pub fn collision(&self, ray: Ray) -> RayCollision {
-        self.body.get_bounding_box().get_ray_collision_box(ray)
-    }
-
-    pub fn decrease_health(&mut self) {
-        self.health -= 10;
-    }
+// 1 if only the first key is held, -1 if only the second one is
+fn key_axis(rl: &RaylibHandle, positive: KeyboardKey, negative: KeyboardKey) -> i8 {
+    rl.is_key_down(positive) as i8 - rl.is_key_down(negative) as i8
 }
 
 pub fn find_angle(vec_1: Vector3, vec_2: Vector3) -> f32 {
@@ -311,14 +379,14 @@ impl Drawable3D for Player {
             angle = 360.0 - angle;
         }
         self.body.draw_target(d, camera, angle);
-        self.pistol
+        self.weapon()
             .draw_target(d, camera, self.camera.position, angle);
 
         // Health
         // Find distance between camera and player
         let distance = (self.camera.position - camera.position).length();
         let rect_size = Vector2::new(1000.0, 200.0) / distance;
-        let health_size = Vector2::new(rect_size.x * self.health as f32 / 100.0, rect_size.y);
+        let health_size = Vector2::new(rect_size.x * self.health_ratio(), rect_size.y);
         let mut health_pos =
             d.get_world_to_screen(self.camera.position + Vector3::new(0.0, 1.0, 0.0), camera);
         health_pos.x -= rect_size.x / 2.0;
@@ -0,0 +1,156 @@
+use crate::gun::{Pistol, Rifle, Shotgun};
+use game_core::weapon::{Ammo, WeaponKind};
+use raylib::camera::Camera3D;
+use raylib::color::Color;
+use raylib::drawing::RaylibMode3DExt;
+use raylib::ffi::{rlPopMatrix, rlPushMatrix, rlRotatef, rlTranslatef};
+use raylib::math::Vector3;
+use raylib::prelude::RaylibDraw3D;
+use raylib::prelude::RaylibDrawHandle;
+
+// ----------------------------------------------------------------------------
+// Weapons
+// The stats come from the WeaponKind the server also knows, so a weapon here
+// only adds its model and the state the player sees while holding it.
+// ----------------------------------------------------------------------------
+
+// Where the model is held, relative to the player's eyes
+pub const HOLD_X_OFFSET: f32 = -0.6;
+pub const HOLD_Y_OFFSET: f32 = -0.7;
+pub const HOLD_Z_OFFSET: f32 = 0.9;
+// How far a weapon kicks back right after a shot
+const RECOIL_DISTANCE: f32 = 0.3;
+// How far a weapon tips down halfway through a reload, in degrees
+const RELOAD_ANGLE: f32 = 60.0;
+
+// One box of a model, offset from where the weapon is held
+pub struct Part {
+    pub offset: Vector3,
+    pub size: Vector3,
+}
+
+// Cooldown and ammo, the same for every weapon
+pub struct WeaponState {
+    // Seconds until the next shot
+    cooldown: f32,
+    ammo: Ammo,
+}
+
+impl WeaponState {
+    pub fn new(kind: WeaponKind) -> Self {
+        WeaponState {
+            cooldown: 0.0,
+            ammo: Ammo::new(kind),
+        }
+    }
+}
+
+pub trait Weapon {
+    fn kind(&self) -> WeaponKind;
+    fn state(&self) -> &WeaponState;
+    fn state_mut(&mut self) -> &mut WeaponState;
+    fn parts(&self) -> &[Part];
+    fn color(&self) -> Color;
+
+    fn ammo(&self) -> &Ammo {
+        &self.state().ammo
+    }
+
+    fn update(&mut self, dt: f32) {
+        let state = self.state_mut();
+        state.cooldown = (state.cooldown - dt).max(0.0);
+        state.ammo.advance(dt);
+    }
+
+    // Returns whether the weapon was ready and loaded, the server checks the
+    // same fire rate and ammo
+    fn fire(&mut self) -> bool {
+        let cooldown = self.kind().cooldown().as_secs_f32();
+        let state = self.state_mut();
+        if state.cooldown > 0.0 || !state.ammo.fire() {
+            return false;
+        }
+        state.cooldown = cooldown;
+        true
+    }
+
+    // Returns whether a reload started
+    fn reload(&mut self) -> bool {
+        self.state_mut().ammo.start_reload()
+    }
+
+    fn put_away(&mut self) {
+        self.state_mut().ammo.cancel_reload();
+    }
+
+    // The server knows better
+    fn set_ammo(&mut self, magazine: u8, reserve: u16) {
+        let ammo = &mut self.state_mut().ammo;
+        ammo.magazine = magazine;
+        ammo.reserve = reserve;
+    }
+
+    // The same pellets the server works out from the aim
+    fn pellet_directions(&self, forward: Vector3) -> Vec<Vector3> {
+        self.kind()
+            .pellet_directions(forward.into())
+            .into_iter()
+            .map(Vector3::from)
+            .collect()
+    }
+
+    fn draw_target(&self, d: &mut RaylibDrawHandle, camera: &Camera3D, pos: Vector3, angle: f32) {
+        // The weapon kicks back and greys out until it can fire again
+        let state = self.state();
+        let cooldown = state.cooldown / self.kind().cooldown().as_secs_f32();
+        let color = self.color().lerp(Color::GRAY, cooldown);
+
+        // Tip the weapon down and bring it back up while reloading
+        let reload_angle = state.ammo.reload_progress().map_or(0.0, |progress| {
+            (progress * std::f32::consts::PI).sin() * RELOAD_ANGLE
+        });
+
+        let mut d = d.begin_mode3D(camera);
+        unsafe {
+            rlPushMatrix();
+            // Translate to player position
+            rlTranslatef(pos.x, pos.y, pos.z);
+            // Rotate around player
+            rlRotatef(angle, 0.0, 1.0, 0.0);
+
+            // Offset to where the weapon is held
+            rlTranslatef(
+                HOLD_X_OFFSET,
+                HOLD_Y_OFFSET,
+                HOLD_Z_OFFSET - RECOIL_DISTANCE * cooldown,
+            );
+            rlRotatef(reload_angle, 1.0, 0.0, 0.0);
+
+            for part in self.parts() {
+                d.draw_cube_v(part.offset, part.size, color);
+                d.draw_cube_wires(
+                    part.offset,
+                    part.size.x,
+                    part.size.y,
+                    part.size.z,
+                    Color::BLACK,
+                );
+            }
+            rlPopMatrix();
+        }
+    }
+}
+
+// A weapon of each kind, in the order of WeaponKind::ALL
+pub fn arsenal() -> Vec<Box<dyn Weapon>> {
+    WeaponKind::ALL
+        .iter()
+        .map(|kind| -> Box<dyn Weapon> {
+            match kind {
+                WeaponKind::Pistol => Box::new(Pistol::default()),
+                WeaponKind::Shotgun => Box::new(Shotgun::default()),
+                WeaponKind::Rifle => Box::new(Rifle::default()),
+            }
+        })
+        .collect()
+}
@@ -5,6 +5,14 @@ use std::fmt::Display;
 pub enum ChannelError {
     Io(std::io::Error),
     Bincode,
+    VersionMismatch { local: u32, remote: u32 },
+    FrameTooLarge { size: usize, max: usize },
+}
+
+impl From<std::io::Error> for ChannelError {
+    fn from(value: std::io::Error) -> Self {
+        ChannelError::Io(value)
+    }
 }
 
 impl From<Box<ErrorKind>> for ChannelError {
@@ -21,6 +29,14 @@ impl Display for ChannelError {
         match self {
             ChannelError::Io(error) => error.fmt(f),
             ChannelError::Bincode => writeln!(f, "Bincode failed to (de)serialize"),
+            ChannelError::VersionMismatch { local, remote } => write!(
+                f,
+                "Protocol version mismatch: local v{}, remote v{}",
+                local, remote
+            ),
+            ChannelError::FrameTooLarge { size, max } => {
+                write!(f, "Frame of {} bytes exceeds the maximum of {}", size, max)
+            }
         }
     }
 }
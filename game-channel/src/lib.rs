@@ -1,58 +1,549 @@
 use crate::error::ChannelError;
-use bincode;
-use raylib::math::Vector3;
+use game_core::movement::{Input, MoveState};
+use game_core::weapon::WeaponKind;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
 
 pub mod error;
+pub mod udp;
 
+// Sent over the wire as they are
+pub use game_core::{ChannelVector2, ChannelVector3};
+
+// Bump whenever the wire format of any message changes
+pub const PROTOCOL_VERSION: u32 = 19;
+
+// Assigned by the server when a player joins, unique within a match
+pub type PlayerId = u8;
+
+// Decided by the server and sent to everyone in the match
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Winner {
+    /// Last player standing.
+    Player(PlayerId),
+    /// Last player standing because the remaining opponents disconnected.
+    Forfeit(PlayerId),
+    /// Nobody survived.
+    None,
+}
+
+// Match settings chosen by the server, sent to every player when a match starts
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
-pub struct ChannelVector2 {
-    pub x: f32,
-    pub z: f32,
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    // Seconds before the match starts
+    pub countdown: u8,
+    pub starting_health: u8,
+    // Health lost per pistol hit, the other weapons scale from it
+    pub damage: u8,
+    // Server updates per second
+    pub tick_rate: u32,
+    // Milliseconds remote players are drawn behind the newest snapshot
+    pub interpolation_delay: u32,
 }
 
-impl From<Vector3> for ChannelVector2 {
-    fn from(value: Vector3) -> Self {
-        ChannelVector2 {
-            x: value.x,
-            z: value.z,
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            countdown: 3,
+            starting_health: 100,
+            damage: 10,
+            tick_rate: 60,
+            interpolation_delay: 100,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub enum Winner {
-    Player,
-    Enemy,
-    None,
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Packet {
+    /// Sent once before the enemy positions when a match starts.
+    Rules(Rules),
+    /// The first one a client receives carries its own id and spawn point.
     Player {
-        pos: ChannelVector2,
+        id: PlayerId,
+        pos: ChannelVector3,
         target: ChannelVector2,
+        /// Server time of the snapshot in seconds since the match started.
+        time: f64,
+        weapon: WeaponKind,
+    },
+    /// Movement steps the server has not acknowledged yet, oldest first, only
+    /// ever sent by clients. Sent unreliably, every packet repeats the last
+    /// few steps so a lost one does not hold up the ones after it.
+    Inputs(Vec<Input>),
+    /// Where the server put the receiving player after applying its inputs up
+    /// to and including `sequence`.
+    Position {
+        sequence: u32,
+        state: MoveState,
+    },
+    /// Shot fired by the sending client with its equipped weapon. It starts at
+    /// the player's eyes where the server has them, and the server spreads the
+    /// pellets around the aim. The server decides what it hits, as the players
+    /// stood when the client drew them.
+    Fire {
+        /// Sequence of the last input sent before the shot.
+        after: u32,
+        aim: ChannelVector3,
+        /// Server time of the newest snapshot the client had received.
+        time: f64,
+    },
+    /// The sending client started reloading its equipped weapon.
+    Reload,
+    /// The sending client switched weapons.
+    Equip(WeaponKind),
+    /// Ammo of one of the receiving player's weapons. Sent when a match starts
+    /// and whenever the server turned down a shot or reload the client thought
+    /// it could do.
+    Ammo {
+        weapon: WeaponKind,
+        magazine: u8,
+        reserve: u16,
     },
     Time(u8),
-    Health(u8),
+    /// Health of one player in the match, as decided by the server.
+    Health {
+        id: PlayerId,
+        health: u8,
+    },
+    /// The player disconnected and is no longer part of the match.
+    Left(PlayerId),
+    /// Last packet of a match, apart from the vote on the next map.
     GameOver(Winner),
+    /// The maps players can vote for as the next one, and how many seconds
+    /// the vote is open.
+    MapVote {
+        maps: Vec<String>,
+        seconds: u8,
+    },
+    /// Index of the map the sending client votes for, only ever sent by clients.
+    Vote(u8),
+    /// Votes per map so far, in the order of the `MapVote`.
+    Votes(Vec<u8>),
+    /// The map the next match is played on, sent when the vote closes.
+    NextMap(String),
+    /// The map changed while the receiving player was waiting for a match. Its
+    /// new spawn point follows.
+    Map(MapInfo),
+}
+
+// First message on every connection, sent by the client. Handshakes are encoded
+// on their own rather than as a Packet so that a peer built from a different
+// commit still decodes the protocol version correctly.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub client_build: String,
+    pub player_name: String,
+}
+
+// The map file the server plays on, so every client collides with the same
+// walls.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MapInfo {
+    pub name: String,
+    pub source: String,
+}
+
+impl MapInfo {
+    pub fn new(name: &str, source: &str) -> Self {
+        MapInfo {
+            name: name.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub enum HandshakeReply {
+    /// The client continues over UDP on this server port, or over TCP if none,
+    /// and plays on the given map.
+    Accepted {
+        udp_port: Option<u16>,
+        map: MapInfo,
+    },
+    Rejected {
+        protocol_version: u32,
+    },
 }
 
+// What the game needs from a connection, whichever transport carries it
+pub trait Transport {
+    // Arrives, in order with everything else sent this way
+    fn send(&mut self, packet: Packet) -> Result<(), ChannelError>;
+    // May be lost or arrive out of order. Only for packets that the next one
+    // replaces anyway, or that the receiver can put in order itself.
+    fn send_unreliable(&mut self, packet: Packet) -> Result<(), ChannelError> {
+        self.send(packet)
+    }
+    fn receive(&mut self) -> Result<Packet, ChannelError>;
+    // Push out what is buffered, UDP also resends what was not acknowledged yet
+    fn flush(&mut self) -> Result<(), ChannelError>;
+    // Bytes sent but not yet delivered
+    fn pending_writes(&self) -> usize;
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), ChannelError>;
+    // False once the other side closed the connection
+    fn is_open(&self) -> bool;
+}
+
+// Looks at the stream without consuming anything, it must be non-blocking
+fn peer_open(stream: &TcpStream) -> bool {
+    let mut buf = [0; 1];
+    match stream.peek(&mut buf) {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(e) => e.kind() == ErrorKind::WouldBlock,
+    }
+}
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+const LENGTH_SIZE: usize = 4;
+const READ_CHUNK_SIZE: usize = 4096;
+
+// Every message is sent as a frame: a little endian u32 length followed by the
+// bincode payload. Partial frames are buffered, so a non-blocking stream can
+// return WouldBlock halfway through a frame without corrupting the stream.
 pub struct Channel<T: Read + Write> {
     pub stream: T,
+    max_frame_size: usize,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    // As last set through Transport, streams start out blocking
+    nonblocking: bool,
 }
 
 impl<T: Read + Write> Channel<T> {
     pub fn with_stream(stream: T) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            nonblocking: false,
+        }
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
     }
 
     pub fn send(&mut self, packet: Packet) -> Result<(), ChannelError> {
-        bincode::serialize_into(&mut self.stream, &packet).map_err(|err| err.into())
+        self.send_value(&packet)
     }
 
     pub fn receive(&mut self) -> Result<Packet, ChannelError> {
-        bincode::deserialize_from(&mut self.stream).map_err(|err| err.into())
+        self.receive_value()
+    }
+
+    // Client side of the handshake, returns the server's UDP port if it wants
+    // the rest of the session over UDP, and the map to play on
+    pub fn handshake(
+        &mut self,
+        player_name: &str,
+        client_build: &str,
+    ) -> Result<(Option<u16>, MapInfo), ChannelError> {
+        self.send_value(&Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            client_build: client_build.to_string(),
+            player_name: player_name.to_string(),
+        })?;
+
+        match self.receive_value()? {
+            HandshakeReply::Accepted { udp_port, map } => Ok((udp_port, map)),
+            HandshakeReply::Rejected { protocol_version } => Err(ChannelError::VersionMismatch {
+                local: PROTOCOL_VERSION,
+                remote: protocol_version,
+            }),
+        }
+    }
+
+    // Server side of the handshake, the client is told why it was refused
+    pub fn accept_handshake(
+        &mut self,
+        udp_port: Option<u16>,
+        map: &MapInfo,
+    ) -> Result<Handshake, ChannelError> {
+        let frame = self.receive_frame()?;
+
+        // The version leads the handshake, so it decodes even if the rest changed
+        let protocol_version: u32 = bincode::deserialize(&frame)?;
+        if protocol_version != PROTOCOL_VERSION {
+            self.send_value(&HandshakeReply::Rejected {
+                protocol_version: PROTOCOL_VERSION,
+            })?;
+            return Err(ChannelError::VersionMismatch {
+                local: PROTOCOL_VERSION,
+                remote: protocol_version,
+            });
+        }
+
+        let handshake = bincode::deserialize(&frame)?;
+        self.send_value(&HandshakeReply::Accepted {
+            udp_port,
+            map: map.clone(),
+        })?;
+        Ok(handshake)
+    }
+
+    // Write out as much buffered data as the stream accepts right now
+    pub fn flush(&mut self) -> Result<(), ChannelError> {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => return Err(ChannelError::Io(ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    self.write_buffer.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(ChannelError::Io(e)),
+            }
+        }
+
+        match self.stream.flush() {
+            Err(e) if e.kind() != ErrorKind::WouldBlock => Err(ChannelError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Bytes sent but not yet accepted by the stream
+    pub fn pending_writes(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    fn send_value<V: Serialize>(&mut self, value: &V) -> Result<(), ChannelError> {
+        let payload = bincode::serialize(value)?;
+        if payload.len() > self.max_frame_size {
+            return Err(ChannelError::FrameTooLarge {
+                size: payload.len(),
+                max: self.max_frame_size,
+            });
+        }
+
+        self.write_buffer
+            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.write_buffer.extend_from_slice(&payload);
+        self.flush()
+    }
+
+    fn receive_value<V: DeserializeOwned>(&mut self) -> Result<V, ChannelError> {
+        let frame = self.receive_frame()?;
+        bincode::deserialize(&frame).map_err(|err| err.into())
+    }
+
+    fn receive_frame(&mut self) -> Result<Vec<u8>, ChannelError> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+
+            let mut chunk = [0; READ_CHUNK_SIZE];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ChannelError::Io(ErrorKind::UnexpectedEof.into())),
+                Ok(n) => self.read_buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(ChannelError::Io(e)),
+            }
+        }
+    }
+
+    // Remove the first complete frame from the read buffer, if there is one
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, ChannelError> {
+        if self.read_buffer.len() < LENGTH_SIZE {
+            return Ok(None);
+        }
+
+        let mut length = [0; LENGTH_SIZE];
+        length.copy_from_slice(&self.read_buffer[..LENGTH_SIZE]);
+        let size = u32::from_le_bytes(length) as usize;
+        if size > self.max_frame_size {
+            return Err(ChannelError::FrameTooLarge {
+                size,
+                max: self.max_frame_size,
+            });
+        }
+
+        let end = LENGTH_SIZE + size;
+        if self.read_buffer.len() < end {
+            return Ok(None);
+        }
+
+        let frame = self.read_buffer[LENGTH_SIZE..end].to_vec();
+        self.read_buffer.drain(..end);
+        Ok(Some(frame))
+    }
+}
+
+impl Transport for Channel<TcpStream> {
+    fn send(&mut self, packet: Packet) -> Result<(), ChannelError> {
+        Channel::send(self, packet)
+    }
+
+    fn receive(&mut self) -> Result<Packet, ChannelError> {
+        Channel::receive(self)
+    }
+
+    fn flush(&mut self) -> Result<(), ChannelError> {
+        Channel::flush(self)
+    }
+
+    fn pending_writes(&self) -> usize {
+        Channel::pending_writes(self)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), ChannelError> {
+        self.stream.set_nonblocking(nonblocking)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    // A blocking stream is only non-blocking for the peek, then goes back
+    fn is_open(&self) -> bool {
+        let stream = &self.stream;
+        if self.nonblocking {
+            return peer_open(stream);
+        }
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let open = peer_open(stream);
+        stream.set_nonblocking(false).is_ok() && open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Hands out at most a few bytes per call and WouldBlock once it runs dry,
+    // like a non-blocking socket on a slow network
+    #[derive(Default)]
+    struct SlowStream {
+        incoming: VecDeque<u8>,
+        read_size: usize,
+        written: Vec<u8>,
+        write_budget: usize,
+    }
+
+    impl Read for SlowStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.incoming.is_empty() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let size = buf.len().min(self.read_size).min(self.incoming.len());
+            for (byte, incoming) in buf.iter_mut().zip(self.incoming.drain(..size)) {
+                *byte = incoming;
+            }
+            Ok(size)
+        }
+    }
+
+    impl Write for SlowStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.write_budget == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let size = buf.len().min(self.write_budget);
+            self.write_budget -= size;
+            self.written.extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn channel(incoming: &[u8]) -> Channel<SlowStream> {
+        Channel::with_stream(SlowStream {
+            incoming: incoming.iter().copied().collect(),
+            read_size: 1,
+            write_budget: usize::MAX,
+            ..SlowStream::default()
+        })
+    }
+
+    // The bytes a channel puts on the wire for the value
+    fn framed<V: Serialize>(value: &V) -> Vec<u8> {
+        let mut sender = channel(&[]);
+        sender.send_value(value).unwrap();
+        sender.stream.written
+    }
+
+    fn would_block<V: std::fmt::Debug>(result: Result<V, ChannelError>) -> bool {
+        matches!(result, Err(ChannelError::Io(e)) if e.kind() == ErrorKind::WouldBlock)
+    }
+
+    #[test]
+    fn frames_split_across_reads_arrive_whole() {
+        let mut bytes = framed(&Packet::Time(3));
+        bytes.extend(framed(&Packet::Reload));
+        let split = bytes.len() / 3;
+        let mut receiver = channel(&bytes[..split]);
+
+        assert!(would_block(receiver.receive()));
+        receiver.stream.incoming.extend(&bytes[split..]);
+        assert_eq!(receiver.receive().unwrap(), Packet::Time(3));
+        assert_eq!(receiver.receive().unwrap(), Packet::Reload);
+        assert!(would_block(receiver.receive()));
+    }
+
+    #[test]
+    fn oversized_length_is_refused_before_buffering() {
+        let mut receiver = channel(&(DEFAULT_MAX_FRAME_SIZE as u32 + 1).to_le_bytes());
+        assert!(matches!(
+            receiver.receive(),
+            Err(ChannelError::FrameTooLarge { size, max })
+                if size == DEFAULT_MAX_FRAME_SIZE + 1 && max == DEFAULT_MAX_FRAME_SIZE
+        ));
+
+        let mut sender = channel(&[]);
+        sender.set_max_frame_size(2);
+        assert!(matches!(
+            sender.send(Packet::Time(3)),
+            Err(ChannelError::FrameTooLarge { .. })
+        ));
+        assert_eq!(sender.pending_writes(), 0);
+    }
+
+    #[test]
+    fn blocked_writes_finish_on_flush() {
+        let expected = framed(&Packet::Time(3));
+        let mut sender = channel(&[]);
+        sender.stream.write_budget = 2;
+
+        sender.send(Packet::Time(3)).unwrap();
+        assert_eq!(sender.stream.written, expected[..2]);
+        assert_eq!(sender.pending_writes(), expected.len() - 2);
+
+        sender.stream.write_budget = usize::MAX;
+        sender.flush().unwrap();
+        assert_eq!(sender.stream.written, expected);
+        assert_eq!(sender.pending_writes(), 0);
+    }
+
+    #[test]
+    fn version_mismatch_is_reported_on_both_sides() {
+        let mut server = channel(&framed(&Handshake {
+            protocol_version: PROTOCOL_VERSION + 1,
+            client_build: String::from("test"),
+            player_name: String::from("player"),
+        }));
+        let map = MapInfo::new("map", "");
+        assert!(matches!(
+            server.accept_handshake(None, &map),
+            Err(ChannelError::VersionMismatch { local, remote })
+                if local == PROTOCOL_VERSION && remote == PROTOCOL_VERSION + 1
+        ));
+
+        // The server's answer, as a client of the same version would get it
+        let mut client = channel(&server.stream.written);
+        assert!(matches!(
+            client.handshake("player", "test"),
+            Err(ChannelError::VersionMismatch { local, remote })
+                if local == PROTOCOL_VERSION && remote == PROTOCOL_VERSION
+        ));
     }
 }
@@ -0,0 +1,481 @@
+use crate::error::ChannelError;
+use crate::{peer_open, Packet, Transport, DEFAULT_MAX_FRAME_SIZE};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// ----------------------------------------------------------------------------
+// Datagram transport
+// Snapshots are sent unreliably, a lost one is simply replaced by the next.
+// Everything else goes through a small reliable lane that is resent until
+// acknowledged and delivered in order, split over several datagrams when a
+// packet like the map does not fit in one. The TCP connection used for the
+// handshake stays open next to it, so either side notices when the other one
+// goes away.
+// ----------------------------------------------------------------------------
+
+// Fits in a single unfragmented packet on any sane network
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+// Leaves room for the rest of a reliable datagram around its chunk
+const MAX_CHUNK_SIZE: usize = MAX_DATAGRAM_SIZE - 64;
+// Furthest ahead of the next expected one a reliable chunk is kept, room for
+// two of the largest packets. Anything beyond was not sent by a sane peer.
+const RELIABLE_WINDOW: u32 = 2 * DEFAULT_MAX_FRAME_SIZE.div_ceil(MAX_CHUNK_SIZE) as u32;
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Datagram {
+    // Sent by the client until the server answers, so the server learns its address
+    Hello,
+    Welcome,
+    Unreliable {
+        sequence: u32,
+        packet: Packet,
+    },
+    // A chunk of a serialized packet, the last one completes it
+    Reliable {
+        sequence: u32,
+        last: bool,
+        bytes: Vec<u8>,
+    },
+    Ack {
+        sequence: u32,
+    },
+}
+
+struct Unacked {
+    sequence: u32,
+    datagram: Vec<u8>,
+    sent: Instant,
+}
+
+pub struct UdpChannel {
+    socket: UdpSocket,
+    control: TcpStream,
+    nonblocking: bool,
+    next_unreliable: u32,
+    // Of the newest own position, older ones arriving late are dropped
+    newest_position: Option<u32>,
+    next_reliable: u32,
+    unacked: VecDeque<Unacked>,
+    expected_reliable: u32,
+    out_of_order: BTreeMap<u32, (bool, Vec<u8>)>,
+    // Chunks of a reliable packet whose last chunk has not arrived yet
+    assembling: Vec<u8>,
+    // The rest of a packet that grew too large is dropped up to its last chunk
+    skipping: bool,
+    ready: VecDeque<Result<Packet, ChannelError>>,
+}
+
+impl UdpChannel {
+    // Server side, waits for the client's hello on a socket whose port was
+    // sent in the handshake reply
+    pub fn accept(socket: UdpSocket, control: TcpStream) -> Result<Self, ChannelError> {
+        let client = control.peer_addr()?.ip();
+        socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (size, addr) = socket.recv_from(&mut buf)?;
+            if addr.ip() == client
+                && matches!(bincode::deserialize(&buf[..size]), Ok(Datagram::Hello))
+            {
+                socket.connect(addr)?;
+                break;
+            }
+        }
+
+        let channel = UdpChannel::new(socket, control)?;
+        channel.send_datagram(&Datagram::Welcome)?;
+        Ok(channel)
+    }
+
+    // Client side, keeps saying hello until the server answers
+    pub fn connect(control: TcpStream, port: u16) -> Result<Self, ChannelError> {
+        let server = SocketAddr::new(control.peer_addr()?.ip(), port);
+        let any: IpAddr = match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((any, 0))?;
+        socket.connect(server)?;
+        socket.set_read_timeout(Some(RESEND_INTERVAL))?;
+
+        let hello = bincode::serialize(&Datagram::Hello)?;
+        let start = Instant::now();
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        while start.elapsed() < CONNECT_TIMEOUT {
+            socket.send(&hello)?;
+            match socket.recv(&mut buf) {
+                Ok(size) => {
+                    if let Ok(Datagram::Welcome) = bincode::deserialize(&buf[..size]) {
+                        return UdpChannel::new(socket, control);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => return Err(ChannelError::Io(e)),
+            }
+        }
+        Err(ChannelError::Io(ErrorKind::TimedOut.into()))
+    }
+
+    fn new(socket: UdpSocket, control: TcpStream) -> Result<Self, ChannelError> {
+        socket.set_nonblocking(true)?;
+        control.set_nonblocking(true)?;
+        Ok(UdpChannel {
+            socket,
+            control,
+            nonblocking: false,
+            next_unreliable: 0,
+            newest_position: None,
+            next_reliable: 0,
+            unacked: VecDeque::new(),
+            expected_reliable: 0,
+            out_of_order: BTreeMap::new(),
+            assembling: Vec::new(),
+            skipping: false,
+            ready: VecDeque::new(),
+        })
+    }
+
+    fn send_datagram(&self, datagram: &Datagram) -> Result<Vec<u8>, ChannelError> {
+        let bytes = bincode::serialize(datagram)?;
+        if bytes.len() > MAX_DATAGRAM_SIZE {
+            return Err(ChannelError::FrameTooLarge {
+                size: bytes.len(),
+                max: MAX_DATAGRAM_SIZE,
+            });
+        }
+        transmit(&self.socket, &bytes)?;
+        Ok(bytes)
+    }
+
+    // Read every datagram that has arrived and resend what is overdue
+    fn poll(&mut self) -> Result<(), ChannelError> {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(size) => match bincode::deserialize(&buf[..size]) {
+                    Ok(datagram) => self.handle(datagram)?,
+                    // Whatever it was, it was not from our peer
+                    Err(_) => continue,
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(ChannelError::Io(e)),
+            }
+        }
+
+        let now = Instant::now();
+        for unacked in self.unacked.iter_mut() {
+            if now.duration_since(unacked.sent) >= RESEND_INTERVAL {
+                unacked.sent = now;
+                transmit(&self.socket, &unacked.datagram)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, datagram: Datagram) -> Result<(), ChannelError> {
+        match datagram {
+            // A late position would pull the player back, a newer one already
+            // arrived. Enemy snapshots carry their time and inputs their
+            // sequence, so the game sorts those out itself.
+            Datagram::Unreliable { sequence, packet } => {
+                if let Packet::Position { .. } = packet {
+                    if self
+                        .newest_position
+                        .is_some_and(|newest| sequence.wrapping_sub(newest) as i32 <= 0)
+                    {
+                        return Ok(());
+                    }
+                    self.newest_position = Some(sequence);
+                }
+                self.ready.push_back(Ok(packet));
+            }
+            Datagram::Reliable {
+                sequence,
+                last,
+                bytes,
+            } => {
+                // Left unacknowledged, so it is resent once it fits
+                let ahead = sequence.wrapping_sub(self.expected_reliable) as i32;
+                if ahead >= RELIABLE_WINDOW as i32 {
+                    return Ok(());
+                }
+                // Acknowledge duplicates too, the previous ack may have been lost
+                self.send_datagram(&Datagram::Ack { sequence })?;
+                if ahead >= 0 {
+                    self.out_of_order.insert(sequence, (last, bytes));
+                }
+                while let Some((last, bytes)) = self.out_of_order.remove(&self.expected_reliable) {
+                    self.expected_reliable = self.expected_reliable.wrapping_add(1);
+                    self.assemble(last, bytes);
+                }
+            }
+            Datagram::Ack { sequence } => self.unacked.retain(|u| u.sequence != sequence),
+            // The client did not get our welcome
+            Datagram::Hello => {
+                self.send_datagram(&Datagram::Welcome)?;
+            }
+            // The server answered our hello more than once
+            Datagram::Welcome => (),
+        }
+        Ok(())
+    }
+
+    // A packet that grows too large is reported as soon as it does, one that
+    // does not decode once its last chunk is in
+    fn assemble(&mut self, last: bool, bytes: Vec<u8>) {
+        if self.skipping {
+            self.skipping = !last;
+            return;
+        }
+        let size = self.assembling.len() + bytes.len();
+        if size > DEFAULT_MAX_FRAME_SIZE {
+            self.assembling = Vec::new();
+            self.skipping = !last;
+            self.ready.push_back(Err(ChannelError::FrameTooLarge {
+                size,
+                max: DEFAULT_MAX_FRAME_SIZE,
+            }));
+            return;
+        }
+        self.assembling.extend(bytes);
+        if last {
+            let payload = std::mem::take(&mut self.assembling);
+            self.ready
+                .push_back(bincode::deserialize(&payload).map_err(ChannelError::from));
+        }
+    }
+}
+
+// A full socket buffer loses the datagram just like the network would
+fn transmit(socket: &UdpSocket, bytes: &[u8]) -> Result<(), ChannelError> {
+    match socket.send(bytes) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+        Err(e) => Err(ChannelError::Io(e)),
+    }
+}
+
+impl Transport for UdpChannel {
+    fn send(&mut self, packet: Packet) -> Result<(), ChannelError> {
+        let payload = bincode::serialize(&packet)?;
+        if payload.len() > DEFAULT_MAX_FRAME_SIZE {
+            return Err(ChannelError::FrameTooLarge {
+                size: payload.len(),
+                max: DEFAULT_MAX_FRAME_SIZE,
+            });
+        }
+
+        let count = payload.len().div_ceil(MAX_CHUNK_SIZE).max(1);
+        for index in 0..count {
+            let start = index * MAX_CHUNK_SIZE;
+            let end = payload.len().min(start + MAX_CHUNK_SIZE);
+            let sequence = self.next_reliable;
+            self.next_reliable = sequence.wrapping_add(1);
+            let datagram = self.send_datagram(&Datagram::Reliable {
+                sequence,
+                last: index + 1 == count,
+                bytes: payload[start..end].to_vec(),
+            })?;
+            self.unacked.push_back(Unacked {
+                sequence,
+                datagram,
+                sent: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
+    fn send_unreliable(&mut self, packet: Packet) -> Result<(), ChannelError> {
+        let sequence = self.next_unreliable;
+        self.next_unreliable = sequence.wrapping_add(1);
+        self.send_datagram(&Datagram::Unreliable { sequence, packet })?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Packet, ChannelError> {
+        loop {
+            self.poll()?;
+            if let Some(packet) = self.ready.pop_front() {
+                return packet;
+            }
+            if !peer_open(&self.control) {
+                return Err(ChannelError::Io(ErrorKind::UnexpectedEof.into()));
+            }
+            if self.nonblocking {
+                return Err(ChannelError::Io(ErrorKind::WouldBlock.into()));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ChannelError> {
+        self.poll()
+    }
+
+    fn pending_writes(&self) -> usize {
+        self.unacked.iter().map(|u| u.datagram.len()).sum()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), ChannelError> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        peer_open(&self.control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChannelVector3, MapInfo};
+    use game_core::movement::MoveState;
+    use std::net::TcpListener;
+
+    // Both ends of a session over loopback, the server's first
+    fn pair() -> (UdpChannel, UdpChannel) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client_control = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_control, _) = listener.accept().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || UdpChannel::accept(socket, server_control).unwrap());
+        let client = UdpChannel::connect(client_control, port).unwrap();
+        (server.join().unwrap(), client)
+    }
+
+    fn chunks(packet: &Packet, size: usize) -> Vec<Vec<u8>> {
+        let payload = bincode::serialize(packet).unwrap();
+        payload.chunks(size).map(<[u8]>::to_vec).collect()
+    }
+
+    fn reliable(sequence: u32, last: bool, bytes: &[u8]) -> Datagram {
+        Datagram::Reliable {
+            sequence,
+            last,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    fn position(sequence: u32) -> Datagram {
+        let zero = ChannelVector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        Datagram::Unreliable {
+            sequence,
+            packet: Packet::Position {
+                sequence,
+                state: MoveState {
+                    pos: zero,
+                    velocity: zero,
+                    grounded: true,
+                },
+            },
+        }
+    }
+
+    fn received(channel: &mut UdpChannel) -> Vec<Packet> {
+        channel.ready.drain(..).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn packets_larger_than_a_datagram_arrive_in_chunks() {
+        let (mut server, mut client) = pair();
+        let map = Packet::Map(MapInfo::new("big", &"0".repeat(3 * MAX_CHUNK_SIZE)));
+
+        server.send(map.clone()).unwrap();
+        server.send(Packet::Reload).unwrap();
+        assert!(server.unacked.len() > 4);
+        assert_eq!(client.receive().unwrap(), map);
+        assert_eq!(client.receive().unwrap(), Packet::Reload);
+
+        // Every chunk gets acknowledged
+        let start = Instant::now();
+        while !server.unacked.is_empty() && start.elapsed() < CONNECT_TIMEOUT {
+            server.flush().unwrap();
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(server.pending_writes(), 0);
+    }
+
+    #[test]
+    fn reordered_and_duplicated_chunks_are_delivered_once_in_order() {
+        let (_server, mut client) = pair();
+        let time = chunks(&Packet::Time(3), 2);
+        assert_eq!(time.len(), 3);
+        let reload = chunks(&Packet::Reload, MAX_CHUNK_SIZE);
+
+        client.handle(reliable(3, true, &reload[0])).unwrap();
+        client.handle(reliable(2, true, &time[2])).unwrap();
+        client.handle(reliable(2, true, &time[2])).unwrap();
+        assert!(client.ready.is_empty());
+
+        client.handle(reliable(0, false, &time[0])).unwrap();
+        client.handle(reliable(1, false, &time[1])).unwrap();
+        client.handle(reliable(0, false, &time[0])).unwrap();
+        assert_eq!(received(&mut client), [Packet::Time(3), Packet::Reload]);
+        assert!(client.out_of_order.is_empty());
+    }
+
+    #[test]
+    fn far_ahead_and_oversized_chunks_are_not_kept() {
+        let (_server, mut client) = pair();
+        client
+            .handle(reliable(RELIABLE_WINDOW, true, &[0]))
+            .unwrap();
+        assert!(client.out_of_order.is_empty());
+
+        // Dropped as soon as it is too large, the next packet still arrives
+        let chunk = vec![0; MAX_CHUNK_SIZE];
+        let count = DEFAULT_MAX_FRAME_SIZE / MAX_CHUNK_SIZE + 1;
+        for sequence in 0..count as u32 {
+            client.handle(reliable(sequence, false, &chunk)).unwrap();
+        }
+        assert!(client.assembling.len() <= DEFAULT_MAX_FRAME_SIZE);
+        assert!(matches!(
+            client.ready.pop_front(),
+            Some(Err(ChannelError::FrameTooLarge { .. }))
+        ));
+        client.handle(reliable(count as u32, true, &chunk)).unwrap();
+        let reload = chunks(&Packet::Reload, MAX_CHUNK_SIZE);
+        client
+            .handle(reliable(count as u32 + 1, true, &reload[0]))
+            .unwrap();
+        assert_eq!(received(&mut client), [Packet::Reload]);
+    }
+
+    #[test]
+    fn only_late_positions_are_dropped() {
+        let (_server, mut client) = pair();
+        client.handle(position(2)).unwrap();
+        client.handle(position(1)).unwrap();
+        client
+            .handle(Datagram::Unreliable {
+                sequence: 0,
+                packet: Packet::Time(3),
+            })
+            .unwrap();
+        client.handle(position(3)).unwrap();
+
+        let sequences: Vec<_> = received(&mut client)
+            .into_iter()
+            .map(|packet| match packet {
+                Packet::Position { sequence, .. } => sequence,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(sequences, [2, 0, 3]);
+    }
+}
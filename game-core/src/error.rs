@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+// Lines and columns count from 1, like an editor shows them
+#[derive(Debug)]
+pub enum MapError {
+    Io(std::io::Error),
+    Header {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Cell {
+        line: usize,
+        column: usize,
+        cell: char,
+    },
+    RowLength {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    RowCount {
+        expected: usize,
+        found: usize,
+    },
+    BadUnit(f32),
+    NoSpawns,
+    // Spawns count from 1 too, in the order of the header
+    SpawnFacing(usize),
+    SpawnOutside(usize),
+    SpawnInBlock(usize),
+}
+
+impl From<std::io::Error> for MapError {
+    fn from(value: std::io::Error) -> Self {
+        MapError::Io(value)
+    }
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapError::Io(error) => error.fmt(f),
+            MapError::Header {
+                line,
+                column,
+                message,
+            } => write!(f, "Line {}, column {}: {}", line, column, message),
+            MapError::Cell { line, column, cell } => write!(
+                f,
+                "Line {}, column {}: '{}' is not a digit or a material",
+                line, column, cell
+            ),
+            MapError::RowLength {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: row has {} cells, the map is {} wide",
+                line, found, expected
+            ),
+            MapError::RowCount { expected, found } => {
+                write!(f, "Grid has {} rows, the map is {} long", found, expected)
+            }
+            MapError::BadUnit(unit) => write!(f, "Unit must be above 0, not {}", unit),
+            MapError::NoSpawns => write!(f, "Map has no spawns"),
+            MapError::SpawnFacing(spawn) => write!(f, "Spawn {} faces no direction", spawn),
+            MapError::SpawnOutside(spawn) => write!(f, "Spawn {} is outside the map", spawn),
+            MapError::SpawnInBlock(spawn) => write!(f, "Spawn {} is inside a block", spawn),
+        }
+    }
+}
+impl std::error::Error for MapError {}
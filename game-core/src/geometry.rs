@@ -0,0 +1,184 @@
+use crate::ChannelVector3;
+
+// ----------------------------------------------------------------------------
+// Axis aligned boxes and ray tests shared by client and server
+// ----------------------------------------------------------------------------
+
+pub const PLAYER_UNIT: f32 = 1.0;
+pub const PLAYER_HEIGHT: f32 = 3.5;
+// Above the feet, where the camera sits and shots start
+pub const EYE_HEIGHT: f32 = 3.2;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: ChannelVector3,
+    pub max: ChannelVector3,
+}
+
+impl Aabb {
+    pub fn new(min: ChannelVector3, max: ChannelVector3) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn from_center(center: ChannelVector3, size: ChannelVector3) -> Self {
+        let min = ChannelVector3 {
+            x: center.x - size.x / 2.0,
+            y: center.y - size.y / 2.0,
+            z: center.z - size.z / 2.0,
+        };
+        let max = ChannelVector3 {
+            x: center.x + size.x / 2.0,
+            y: center.y + size.y / 2.0,
+            z: center.z + size.z / 2.0,
+        };
+        Aabb { min, max }
+    }
+
+    pub fn center(&self) -> ChannelVector3 {
+        ChannelVector3 {
+            x: (self.min.x + self.max.x) / 2.0,
+            y: (self.min.y + self.max.y) / 2.0,
+            z: (self.min.z + self.max.z) / 2.0,
+        }
+    }
+
+    pub fn size(&self) -> ChannelVector3 {
+        ChannelVector3 {
+            x: self.max.x - self.min.x,
+            y: self.max.y - self.min.y,
+            z: self.max.z - self.min.z,
+        }
+    }
+
+    // Touching does not count, so a player can stand on a block
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.max.x > other.min.x
+            && self.min.x < other.max.x
+            && self.max.y > other.min.y
+            && self.min.y < other.max.y
+            && self.max.z > other.min.z
+            && self.min.z < other.max.z
+    }
+
+    // Distance along the ray to the first intersection, using the slab method
+    pub fn ray_distance(&self, origin: ChannelVector3, direction: ChannelVector3) -> Option<f32> {
+        let slabs = [
+            (origin.x, direction.x, self.min.x, self.max.x),
+            (origin.y, direction.y, self.min.y, self.max.y),
+            (origin.z, direction.z, self.min.z, self.max.z),
+        ];
+
+        let mut t_near = 0.0_f32;
+        let mut t_far = f32::INFINITY;
+        for (origin, direction, min, max) in slabs {
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let t1 = (min - origin) / direction;
+            let t2 = (max - origin) / direction;
+            t_near = t_near.max(t1.min(t2));
+            t_far = t_far.min(t1.max(t2));
+        }
+
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+// The box of a player standing with their feet at pos
+pub fn player_box(pos: ChannelVector3) -> Aabb {
+    let half = PLAYER_UNIT / 2.0;
+    Aabb::new(
+        ChannelVector3 {
+            x: pos.x - half,
+            y: pos.y,
+            z: pos.z - half,
+        },
+        ChannelVector3 {
+            x: pos.x + half,
+            y: pos.y + PLAYER_HEIGHT,
+            z: pos.z + half,
+        },
+    )
+}
+
+// Index of the nearest target along the ray within range, as long as nothing
+// in the map is closer. The range is in lengths of the direction.
+pub fn first_hit(
+    origin: ChannelVector3,
+    direction: ChannelVector3,
+    range: f32,
+    targets: &[Aabb],
+    obstacles: &[Aabb],
+) -> Option<usize> {
+    let (hit, distance) = targets
+        .iter()
+        .enumerate()
+        .filter_map(|(i, target)| Some((i, target.ray_distance(origin, direction)?)))
+        .filter(|(_, distance)| *distance <= range)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    obstacles
+        .iter()
+        .filter_map(|obstacle| obstacle.ray_distance(origin, direction))
+        .all(|obstacle_distance| obstacle_distance > distance)
+        .then_some(hit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(x: f32, y: f32, z: f32) -> ChannelVector3 {
+        ChannelVector3 { x, y, z }
+    }
+
+    const ORIGIN: ChannelVector3 = ChannelVector3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+    const FORWARD: ChannelVector3 = ChannelVector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    #[test]
+    fn hits_the_nearest_target() {
+        let targets = [
+            player_box(vector(0.0, 0.0, 20.0)),
+            player_box(vector(0.0, 0.0, 10.0)),
+        ];
+        assert_eq!(first_hit(ORIGIN, FORWARD, 40.0, &targets, &[]), Some(1));
+    }
+
+    #[test]
+    fn misses_beyond_range() {
+        let targets = [player_box(vector(0.0, 0.0, 10.0))];
+        assert_eq!(first_hit(ORIGIN, FORWARD, 5.0, &targets, &[]), None);
+    }
+
+    #[test]
+    fn obstacle_blocks_the_shot() {
+        let targets = [player_box(vector(0.0, 0.0, 10.0))];
+        let wall = Aabb::new(vector(-2.0, 0.0, 5.0), vector(2.0, 4.0, 6.0));
+        assert_eq!(first_hit(ORIGIN, FORWARD, 40.0, &targets, &[wall]), None);
+
+        // Behind the target it does not matter
+        let wall = Aabb::new(vector(-2.0, 0.0, 15.0), vector(2.0, 4.0, 16.0));
+        assert_eq!(first_hit(ORIGIN, FORWARD, 40.0, &targets, &[wall]), Some(0));
+    }
+
+    #[test]
+    fn standing_on_a_block_is_not_intersecting() {
+        let block = Aabb::new(vector(-1.0, 0.0, -1.0), vector(1.0, 2.0, 1.0));
+        assert!(!player_box(vector(0.0, 2.0, 0.0)).intersects(&block));
+        assert!(player_box(vector(0.0, 1.9, 0.0)).intersects(&block));
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(feature = "raylib")]
+use raylib::math::Vector3;
+use serde::{Deserialize, Serialize};
+use std::ops::Add;
+
+// ----------------------------------------------------------------------------
+// Game simulation shared by client and server
+// Plain Rust without any drawing, so the server can run the same movement and
+// hit tests the client predicts with.
+// ----------------------------------------------------------------------------
+
+pub mod error;
+pub mod geometry;
+pub mod map;
+pub mod movement;
+pub mod weapon;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct ChannelVector2 {
+    pub x: f32,
+    pub z: f32,
+}
+
+impl ChannelVector2 {
+    pub fn lerp(self, other: ChannelVector2, t: f32) -> ChannelVector2 {
+        ChannelVector2 {
+            x: self.x + (other.x - self.x) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+}
+
+#[cfg(feature = "raylib")]
+impl From<Vector3> for ChannelVector2 {
+    fn from(value: Vector3) -> Self {
+        ChannelVector2 {
+            x: value.x,
+            z: value.z,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct ChannelVector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl ChannelVector3 {
+    pub fn lerp(self, other: ChannelVector3, t: f32) -> ChannelVector3 {
+        ChannelVector3 {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+
+    pub fn scale(self, factor: f32) -> ChannelVector3 {
+        ChannelVector3 {
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+        }
+    }
+
+    pub fn dot(self, other: ChannelVector3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: ChannelVector3) -> ChannelVector3 {
+        ChannelVector3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    // None for a vector without a direction
+    pub fn normalized(self) -> Option<ChannelVector3> {
+        let length = self.length();
+        length.is_normal().then(|| self.scale(1.0 / length))
+    }
+
+    // Around a normalized axis, counterclockwise looking down the axis
+    pub fn rotated(self, axis: ChannelVector3, angle: f32) -> ChannelVector3 {
+        let (sin, cos) = angle.sin_cos();
+        self.scale(cos) + axis.cross(self).scale(sin) + axis.scale(axis.dot(self) * (1.0 - cos))
+    }
+}
+
+impl Add for ChannelVector3 {
+    type Output = ChannelVector3;
+
+    fn add(self, other: ChannelVector3) -> ChannelVector3 {
+        ChannelVector3 {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+#[cfg(feature = "raylib")]
+impl From<Vector3> for ChannelVector3 {
+    fn from(value: Vector3) -> Self {
+        ChannelVector3 {
+            x: value.x,
+            y: value.y,
+            z: value.z,
+        }
+    }
+}
+
+#[cfg(feature = "raylib")]
+impl From<ChannelVector3> for Vector3 {
+    fn from(value: ChannelVector3) -> Self {
+        Vector3::new(value.x, value.y, value.z)
+    }
+}
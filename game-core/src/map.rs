@@ -0,0 +1,508 @@
+use crate::error::MapError;
+use crate::geometry::{self, Aabb};
+use crate::{ChannelVector2, ChannelVector3};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+// ----------------------------------------------------------------------------
+// Map geometry shared by client and server
+// A map file is a TOML header, a line with just `---`, then the grid. Every
+// cell of the grid is a material, digits are blocks that many units high
+// unless the header says otherwise. A file with only a digit grid is a map
+// with the default header. Every row must be as wide as the map.
+// ----------------------------------------------------------------------------
+
+pub const SEPARATOR: &str = "---";
+
+// Colors are RGB, so the client can draw them however it likes
+pub const BLOCK_COLOR: [u8; 3] = [230, 41, 55];
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Header {
+    pub name: String,
+    // In cells, taken from the grid when left out
+    pub width: Option<usize>,
+    pub length: Option<usize>,
+    // Size of a cell in world units
+    pub unit: f32,
+    // In units
+    pub wall_height: u32,
+    // Placed around the edge when left out, see Layout::default_spawns
+    pub spawns: Option<Vec<Spawn>>,
+    pub materials: HashMap<char, Material>,
+    pub pickups: Vec<Pickup>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            name: String::from("Untitled"),
+            width: None,
+            length: None,
+            unit: 2.0,
+            // High enough that jumping off a block does not get anyone out
+            wall_height: 4,
+            spawns: None,
+            materials: HashMap::new(),
+            pickups: Vec::new(),
+        }
+    }
+}
+
+// Where a player starts, players take turns between the teams
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Spawn {
+    pub team: u8,
+    pub pos: ChannelVector2,
+    pub facing: ChannelVector2,
+}
+
+impl Spawn {
+    // A point to look at, as the server sends it
+    pub fn target(&self) -> ChannelVector2 {
+        let length = (self.facing.x * self.facing.x + self.facing.z * self.facing.z).sqrt();
+        ChannelVector2 {
+            x: self.pos.x + self.facing.x / length,
+            z: self.pos.z + self.facing.z / length,
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    // In units, 0 leaves the cell empty
+    pub height: u32,
+    #[serde(default = "block_color")]
+    pub color: [u8; 3],
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PickupKind {
+    Health,
+    Ammo,
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub pos: ChannelVector2,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Block {
+    pub aabb: Aabb,
+    pub color: [u8; 3],
+}
+
+// Everything the client draws and the server simulates, built from a map file
+#[derive(PartialEq, Debug, Clone)]
+pub struct Layout {
+    pub name: String,
+    pub unit: f32,
+    // In cells
+    pub width: usize,
+    pub length: usize,
+    pub wall_height: f32,
+    pub spawns: Vec<Spawn>,
+    pub pickups: Vec<Pickup>,
+    pub blocks: Vec<Block>,
+}
+
+impl Layout {
+    pub fn load(path: &Path) -> Result<Layout, MapError> {
+        Layout::parse(&read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Layout, MapError> {
+        let lines: Vec<&str> = source.lines().collect();
+        let (header, first_row) = match lines.iter().position(|line| line.trim() == SEPARATOR) {
+            Some(separator) => {
+                let header = lines[..separator].join("\n");
+                let header = toml::from_str(&header).map_err(|e| header_error(&header, e))?;
+                (header, separator + 1)
+            }
+            None => (Header::default(), 0),
+        };
+        if !(header.unit.is_finite() && header.unit > 0.0) {
+            return Err(MapError::BadUnit(header.unit));
+        }
+        if header.spawns.as_ref().is_some_and(Vec::is_empty) {
+            return Err(MapError::NoSpawns);
+        }
+
+        // A blank line or two at the end is not another row
+        let mut grid = &lines[first_row..];
+        while grid.last().is_some_and(|row| row.trim().is_empty()) {
+            grid = &grid[..grid.len() - 1];
+        }
+
+        let width = header
+            .width
+            .unwrap_or_else(|| grid.first().map_or(0, |row| row.chars().count()));
+        let length = header.length.unwrap_or(grid.len());
+        if grid.len() != length {
+            return Err(MapError::RowCount {
+                expected: length,
+                found: grid.len(),
+            });
+        }
+
+        let mut layout = Layout {
+            name: header.name,
+            unit: header.unit,
+            width,
+            length,
+            wall_height: header.wall_height as f32 * header.unit,
+            spawns: Vec::new(),
+            pickups: header.pickups,
+            blocks: Vec::new(),
+        };
+
+        for (i, row) in grid.iter().enumerate() {
+            let line = first_row + i + 1;
+            let found = row.chars().count();
+            if found != width {
+                return Err(MapError::RowLength {
+                    line,
+                    expected: width,
+                    found,
+                });
+            }
+
+            for (j, cell) in row.chars().enumerate() {
+                let material = header
+                    .materials
+                    .get(&cell)
+                    .copied()
+                    .or_else(|| {
+                        cell.to_digit(10).map(|height| Material {
+                            height,
+                            color: BLOCK_COLOR,
+                        })
+                    })
+                    .ok_or(MapError::Cell {
+                        line,
+                        column: j + 1,
+                        cell,
+                    })?;
+                if material.height != 0 {
+                    layout.blocks.push(layout.block(i, j, material));
+                }
+            }
+        }
+
+        match header.spawns {
+            Some(spawns) => {
+                for (i, spawn) in spawns.iter().enumerate() {
+                    layout.check_spawn(spawn).map_err(|error| error(i + 1))?;
+                }
+                layout.spawns = spawns;
+            }
+            // Nobody wrote these, so the ones that do not fit are left out
+            None => {
+                layout.spawns = layout
+                    .default_spawns()
+                    .into_iter()
+                    .filter(|spawn| layout.check_spawn(spawn).is_ok())
+                    .collect();
+                if layout.spawns.is_empty() {
+                    return Err(MapError::NoSpawns);
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    // Size of the floor in world units
+    pub fn size(&self) -> ChannelVector2 {
+        ChannelVector2 {
+            x: self.width as f32 * self.unit,
+            z: self.length as f32 * self.unit,
+        }
+    }
+
+    // The n-th player to join spawns here
+    pub fn spawn(&self, player: usize) -> Option<Spawn> {
+        let mut teams: Vec<u8> = self.spawns.iter().map(|spawn| spawn.team).collect();
+        teams.sort_unstable();
+        teams.dedup();
+        let team = *teams.get(player % teams.len().max(1))?;
+
+        let spawns: Vec<&Spawn> = self
+            .spawns
+            .iter()
+            .filter(|spawn| spawn.team == team)
+            .collect();
+        Some(*spawns[player / teams.len() % spawns.len()])
+    }
+
+    pub fn walls(&self) -> Vec<Aabb> {
+        let size = self.size();
+        let x_offset = size.x / 2.0 + self.unit / 2.0;
+        let z_offset = size.z / 2.0 + self.unit / 2.0;
+        let x_wall = ChannelVector3 {
+            x: self.unit,
+            y: self.wall_height,
+            z: size.z,
+        };
+        let z_wall = ChannelVector3 {
+            x: size.x,
+            y: self.wall_height,
+            z: self.unit,
+        };
+
+        vec![
+            Aabb::from_center(
+                ChannelVector3 {
+                    x: x_offset,
+                    y: self.wall_height / 2.0,
+                    z: 0.0,
+                },
+                x_wall,
+            ),
+            Aabb::from_center(
+                ChannelVector3 {
+                    x: -x_offset,
+                    y: self.wall_height / 2.0,
+                    z: 0.0,
+                },
+                x_wall,
+            ),
+            Aabb::from_center(
+                ChannelVector3 {
+                    x: 0.0,
+                    y: self.wall_height / 2.0,
+                    z: z_offset,
+                },
+                z_wall,
+            ),
+            Aabb::from_center(
+                ChannelVector3 {
+                    x: 0.0,
+                    y: self.wall_height / 2.0,
+                    z: -z_offset,
+                },
+                z_wall,
+            ),
+        ]
+    }
+
+    // Walls and blocks, everything players collide with and shots stop at
+    pub fn obstacles(&self) -> Vec<Aabb> {
+        let mut obstacles = self.walls();
+        obstacles.extend(self.blocks.iter().map(|block| block.aabb));
+        obstacles
+    }
+
+    // Around the edge facing the middle, the old maps had no spawns of their
+    // own. Sides a cell in from the walls, corners a cell and a half.
+    fn default_spawns(&self) -> Vec<Spawn> {
+        let size = self.size();
+        let side_x = size.x / 2.0 - self.unit;
+        let side_z = size.z / 2.0 - self.unit;
+        let corner_x = size.x / 2.0 - 1.5 * self.unit;
+        let corner_z = size.z / 2.0 - 1.5 * self.unit;
+        let spawn = |team, x, z, facing_x, facing_z| Spawn {
+            team,
+            pos: ChannelVector2 { x, z },
+            facing: ChannelVector2 {
+                x: facing_x,
+                z: facing_z,
+            },
+        };
+        vec![
+            spawn(0, 0.0, side_z, 0.0, -1.0),
+            spawn(1, 0.0, -side_z, 0.0, 1.0),
+            spawn(0, side_x, 0.0, -1.0, 0.0),
+            spawn(1, -side_x, 0.0, 1.0, 0.0),
+            spawn(0, corner_x, corner_z, -1.0, -1.0),
+            spawn(1, -corner_x, -corner_z, 1.0, 1.0),
+            spawn(0, corner_x, -corner_z, -1.0, 1.0),
+            spawn(1, -corner_x, corner_z, 1.0, -1.0),
+        ]
+    }
+
+    // A player must fit on the floor there and be able to look somewhere
+    fn check_spawn(&self, spawn: &Spawn) -> Result<(), fn(usize) -> MapError> {
+        let facing = spawn.facing;
+        if !(facing.x * facing.x + facing.z * facing.z)
+            .sqrt()
+            .is_normal()
+        {
+            return Err(MapError::SpawnFacing);
+        }
+
+        let player = geometry::player_box(ChannelVector3 {
+            x: spawn.pos.x,
+            y: 0.0,
+            z: spawn.pos.z,
+        });
+        let size = self.size();
+        let inside = player.min.x >= -size.x / 2.0
+            && player.max.x <= size.x / 2.0
+            && player.min.z >= -size.z / 2.0
+            && player.max.z <= size.z / 2.0;
+        if !inside {
+            return Err(MapError::SpawnOutside);
+        }
+        if self
+            .blocks
+            .iter()
+            .any(|block| player.intersects(&block.aabb))
+        {
+            return Err(MapError::SpawnInBlock);
+        }
+        Ok(())
+    }
+
+    fn block(&self, row: usize, column: usize, material: Material) -> Block {
+        let size = self.size();
+        let block_size = ChannelVector3 {
+            x: self.unit,
+            y: self.unit * material.height as f32,
+            z: self.unit,
+        };
+        let pos = ChannelVector3 {
+            x: column as f32 * self.unit - size.x / 2.0 + self.unit / 2.0,
+            y: block_size.y / 2.0,
+            z: row as f32 * self.unit - size.z / 2.0 + self.unit / 2.0,
+        };
+        Block {
+            aabb: Aabb::from_center(pos, block_size),
+            color: material.color,
+        }
+    }
+}
+
+// toml points at a byte range, editors at lines and columns
+fn header_error(header: &str, error: toml::de::Error) -> MapError {
+    let offset = error.span().map_or(0, |span| span.start);
+    let before = &header[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |last| last.chars().count())
+        + 1;
+    MapError::Header {
+        line,
+        column,
+        message: error.message().to_string(),
+    }
+}
+
+fn block_color() -> [u8; 3] {
+    BLOCK_COLOR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: &str = "---\n0000\n0000\n0010\n0000\n";
+
+    fn with_spawn(spawn: &str) -> Result<Layout, MapError> {
+        Layout::parse(&format!("[[spawns]]\nteam = 0\n{}\n{}", spawn, GRID))
+    }
+
+    #[test]
+    fn maps_in_resources_load() {
+        for source in [
+            include_str!("../../resources/map.txt"),
+            include_str!("../../resources/pillars.txt"),
+        ] {
+            let layout = Layout::parse(source).unwrap();
+            assert!(layout.spawn(0).is_some());
+        }
+    }
+
+    #[test]
+    fn bad_cell_has_line_and_column() {
+        let source = "name = \"Bad\"\n---\n0000\n00x0\n0000\n";
+        match Layout::parse(source) {
+            Err(MapError::Cell { line, column, cell }) => {
+                assert_eq!((line, column, cell), (4, 3, 'x'));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn ragged_row_has_line() {
+        let source = "0000\n000\n0000\n";
+        match Layout::parse(source) {
+            Err(MapError::RowLength {
+                line,
+                expected,
+                found,
+            }) => assert_eq!((line, expected, found), (2, 4, 3)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_header_has_line_and_column() {
+        let source = "name = \"Bad\"\nunit = \"two\"\n---\n0000\n";
+        match Layout::parse(source) {
+            Err(MapError::Header { line, column, .. }) => {
+                assert_eq!((line, column), (2, 8));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn trailing_blank_lines_are_not_rows() {
+        let layout = Layout::parse("0000\n0000\n0000\n\n\n").unwrap();
+        assert_eq!((layout.width, layout.length), (4, 3));
+    }
+
+    #[test]
+    fn digit_grids_get_spawns_that_fit() {
+        // The old 20 by 20 maps keep the spawns they always had
+        let grid = vec!["0".repeat(20); 20].join("\n");
+        let layout = Layout::parse(&grid).unwrap();
+        assert_eq!(layout.spawns.len(), 8);
+        assert_eq!(layout.spawns[0].pos, ChannelVector2 { x: 0.0, z: 18.0 });
+        assert_eq!(layout.spawns[4].pos, ChannelVector2 { x: 17.0, z: 17.0 });
+
+        // The block covers the spawn on the near side, from 0 to 2 on x and
+        // 4 to 6 on z
+        let layout = Layout::parse("000000\n000000\n000000\n000000\n000000\n000100\n").unwrap();
+        assert_eq!(layout.spawns.len(), 7);
+        assert!(layout
+            .spawns
+            .iter()
+            .all(|spawn| spawn.pos.x.abs() < 6.0 && spawn.pos.z.abs() < 6.0));
+        assert_eq!(layout.spawns[0].pos, ChannelVector2 { x: 0.0, z: -4.0 });
+    }
+
+    #[test]
+    fn spawn_must_face_somewhere() {
+        let error = with_spawn("pos = { x = -2.0, z = -2.0 }\nfacing = { x = 0.0, z = 0.0 }");
+        assert!(matches!(error, Err(MapError::SpawnFacing(1))));
+    }
+
+    #[test]
+    fn spawn_must_be_on_the_floor() {
+        let error = with_spawn("pos = { x = 4.0, z = 0.0 }\nfacing = { x = 0.0, z = 1.0 }");
+        assert!(matches!(error, Err(MapError::SpawnOutside(1))));
+    }
+
+    #[test]
+    fn spawn_must_not_be_in_a_block() {
+        // The block is in the third row and column, from 0 to 2 on both axes
+        let error = with_spawn("pos = { x = 1.0, z = 1.0 }\nfacing = { x = 0.0, z = 1.0 }");
+        assert!(matches!(error, Err(MapError::SpawnInBlock(1))));
+
+        let layout = with_spawn("pos = { x = -2.0, z = -2.0 }\nfacing = { x = 0.0, z = 1.0 }");
+        assert_eq!(layout.unwrap().spawns.len(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::geometry::{self, Aabb};
+use crate::{ChannelVector2, ChannelVector3};
+use serde::{Deserialize, Serialize};
+
+// ----------------------------------------------------------------------------
+// Player movement shared by client and server
+// The client predicts its own movement with this and the server replays the
+// same inputs to decide where the player really is, so both must agree exactly.
+// Inspiration from https://gist.github.com/jakubtomsu/9cae5298f86d2b9d2aed48641a1a3dbd
+// ----------------------------------------------------------------------------
+
+// Movement always advances by this much, however fast frames are drawn, so it
+// plays the same at any frame rate and the server can replay it exactly
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+// A slow frame runs at most this many steps, the rest of it is dropped
+const MAX_STEPS: u32 = 15;
+// Frame times add up with rounding errors, so a step that is due is taken
+// even when this much of it is missing
+const STEP_TOLERANCE: f32 = 1e-3;
+
+pub const SPEED: f32 = 90.0;
+// Horizontal velocity decays by e^-DAMPING every second
+pub const DAMPING: f32 = 10.0;
+pub const GRAVITY: f32 = 30.0;
+// Just enough to get onto the low blocks, the walls are too high to jump over
+pub const JUMP_SPEED: f32 = 12.0;
+
+// What the player did during one step, numbered so the server can say which
+// inputs it has applied
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Input {
+    pub sequence: u32,
+    // -1, 0 or 1, from the movement keys
+    pub forward: i8,
+    pub right: i8,
+    pub jump: bool,
+    // Horizontal view direction, normalized
+    pub facing: ChannelVector2,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct MoveState {
+    // Feet of the player
+    pub pos: ChannelVector3,
+    pub velocity: ChannelVector3,
+    // Standing on the floor or a block, only then can the player jump
+    pub grounded: bool,
+}
+
+impl MoveState {
+    pub fn at(pos: ChannelVector3) -> Self {
+        MoveState {
+            pos,
+            velocity: ChannelVector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            grounded: true,
+        }
+    }
+}
+
+// Collects frame times and hands them out as whole steps
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Timestep {
+    accumulated: f32,
+}
+
+impl Timestep {
+    // How many steps are due after a frame that took dt
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulated += dt.max(0.0);
+        let steps = (self.accumulated / TIMESTEP + STEP_TOLERANCE) as u32;
+        self.accumulated = (self.accumulated - steps as f32 * TIMESTEP).max(0.0);
+        steps.min(MAX_STEPS)
+    }
+
+    // How far into the next step the frame is, for drawing between steps
+    pub fn alpha(&self) -> f32 {
+        self.accumulated / TIMESTEP
+    }
+}
+
+pub fn step(state: &mut MoveState, input: &Input, obstacles: &[Aabb]) {
+    let dt = TIMESTEP;
+    let facing = input.facing;
+    let right = ChannelVector2 {
+        x: -facing.z,
+        z: facing.x,
+    };
+    let forward = input.forward.signum() as f32;
+    let strafe = input.right.signum() as f32;
+
+    let speed = dt * SPEED;
+    state.velocity.x += (facing.x * forward + right.x * strafe) * speed;
+    state.velocity.z += (facing.z * forward + right.z * strafe) * speed;
+
+    // Damping
+    let damping = (-DAMPING * dt).exp();
+    state.velocity.x *= damping;
+    state.velocity.z *= damping;
+
+    // Gravity
+    if input.jump && state.grounded {
+        state.velocity.y = JUMP_SPEED;
+    }
+    state.velocity.y -= GRAVITY * dt;
+
+    // Displacement, each horizontal axis is stopped on its own so the player
+    // slides along walls
+    let mut displacement = ChannelVector3 {
+        x: state.velocity.x * dt,
+        y: state.velocity.y * dt,
+        z: state.velocity.z * dt,
+    };
+    let pos = state.pos;
+    if !colliding(
+        ChannelVector3 {
+            x: pos.x + displacement.x,
+            ..pos
+        },
+        obstacles,
+    )
+    .is_empty()
+    {
+        displacement.x = 0.0;
+    }
+    if !colliding(
+        ChannelVector3 {
+            z: pos.z + displacement.z,
+            ..pos
+        },
+        obstacles,
+    )
+    .is_empty()
+    {
+        displacement.z = 0.0;
+    }
+    state.pos.x += displacement.x;
+    state.pos.z += displacement.z;
+
+    // Then vertically, landing on top of whatever the player falls onto
+    state.grounded = false;
+    let fallen = ChannelVector3 {
+        y: state.pos.y + displacement.y,
+        ..state.pos
+    };
+    let below = colliding(fallen, obstacles);
+    if fallen.y <= 0.0 {
+        state.pos.y = 0.0;
+        state.velocity.y = 0.0;
+        state.grounded = true;
+    } else if below.is_empty() {
+        state.pos.y = fallen.y;
+    } else {
+        if displacement.y < 0.0 {
+            state.pos.y = below
+                .iter()
+                .map(|obstacle| obstacle.max.y)
+                .fold(state.pos.y + displacement.y, f32::max);
+            state.grounded = true;
+        }
+        // Bumping a head stops the jump just the same
+        state.velocity.y = 0.0;
+    }
+}
+
+fn colliding(pos: ChannelVector3, obstacles: &[Aabb]) -> Vec<&Aabb> {
+    let player = geometry::player_box(pos);
+    obstacles
+        .iter()
+        .filter(|obstacle| player.intersects(obstacle))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn still(sequence: u32) -> Input {
+        Input {
+            sequence,
+            forward: 0,
+            right: 0,
+            jump: false,
+            facing: ChannelVector2 { x: 0.0, z: 1.0 },
+        }
+    }
+
+    fn vector(x: f32, y: f32, z: f32) -> ChannelVector3 {
+        ChannelVector3 { x, y, z }
+    }
+
+    // Two seconds of frames at the given rate
+    fn steps_at(fps: u32) -> u32 {
+        let mut timestep = Timestep::default();
+        (0..fps * 2)
+            .map(|_| timestep.advance(1.0 / fps as f32))
+            .sum()
+    }
+
+    #[test]
+    fn same_steps_at_any_frame_rate() {
+        assert_eq!(steps_at(30), 120);
+        assert_eq!(steps_at(60), 120);
+        assert_eq!(steps_at(144), 120);
+    }
+
+    #[test]
+    fn slow_frames_are_capped() {
+        let mut timestep = Timestep::default();
+        assert_eq!(timestep.advance(10.0), MAX_STEPS);
+    }
+
+    #[test]
+    fn damping_over_one_second() {
+        let mut state = MoveState::at(vector(0.0, 0.0, 0.0));
+        state.velocity.x = 10.0;
+        for sequence in 1..=60 {
+            step(&mut state, &still(sequence), &[]);
+        }
+        let expected = 10.0 * (-DAMPING).exp();
+        assert!((state.velocity.x - expected).abs() < expected * 1e-3);
+        assert!(state.grounded);
+    }
+
+    #[test]
+    fn lands_on_a_block() {
+        let block = Aabb::new(vector(-2.0, 0.0, -2.0), vector(2.0, 2.0, 2.0));
+        let mut state = MoveState::at(vector(0.0, 3.0, 0.0));
+        state.grounded = false;
+        for sequence in 1..=60 {
+            step(&mut state, &still(sequence), &[block]);
+        }
+        assert_eq!(state.pos.y, 2.0);
+        assert_eq!(state.velocity.y, 0.0);
+        assert!(state.grounded);
+    }
+
+    #[test]
+    fn jumps_onto_a_low_block() {
+        let block = Aabb::new(vector(-2.0, 0.0, 1.0), vector(2.0, 2.0, 50.0));
+        let mut state = MoveState::at(vector(0.0, 0.0, 0.0));
+        for sequence in 1..=30 {
+            let input = Input {
+                forward: 1,
+                jump: true,
+                ..still(sequence)
+            };
+            step(&mut state, &input, &[block]);
+        }
+        for sequence in 31..=90 {
+            step(&mut state, &still(sequence), &[block]);
+        }
+        assert_eq!(state.pos.y, 2.0);
+        assert!(state.pos.z > 1.0);
+        assert!(state.grounded);
+    }
+
+    #[test]
+    fn walls_stop_the_player() {
+        let wall = Aabb::new(vector(-5.0, 0.0, 2.0), vector(5.0, 10.0, 3.0));
+        let mut state = MoveState::at(vector(0.0, 0.0, 0.0));
+        for sequence in 1..=120 {
+            let input = Input {
+                forward: 1,
+                ..still(sequence)
+            };
+            step(&mut state, &input, &[wall]);
+        }
+        assert!(state.pos.z + geometry::PLAYER_UNIT / 2.0 <= 2.0);
+        assert!(state.pos.z > 1.0);
+    }
+}
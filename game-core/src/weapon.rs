@@ -0,0 +1,255 @@
+use crate::ChannelVector3;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// ----------------------------------------------------------------------------
+// Weapon stats shared by client and server
+// Both sides run the same ammo rules on the same frame times, so the client
+// can show its ammo right away and the server only has to speak up when they
+// disagree.
+// ----------------------------------------------------------------------------
+
+// Every player carries one of each
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum WeaponKind {
+    Pistol,
+    Shotgun,
+    Rifle,
+}
+
+impl WeaponKind {
+    // In the order of the number keys that equip them
+    pub const ALL: [WeaponKind; 3] = [WeaponKind::Pistol, WeaponKind::Shotgun, WeaponKind::Rifle];
+
+    pub fn index(self) -> usize {
+        match self {
+            WeaponKind::Pistol => 0,
+            WeaponKind::Shotgun => 1,
+            WeaponKind::Rifle => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WeaponKind::Pistol => "PISTOL",
+            WeaponKind::Shotgun => "SHOTGUN",
+            WeaponKind::Rifle => "RIFLE",
+        }
+    }
+
+    // Health lost per pellet that hits, scaled from the damage of a pistol
+    // hit set in the match rules
+    pub fn damage(self, pistol_damage: u8) -> u8 {
+        match self {
+            WeaponKind::Pistol => pistol_damage,
+            WeaponKind::Shotgun => (pistol_damage / 2).max(1),
+            WeaponKind::Rifle => pistol_damage.saturating_mul(3),
+        }
+    }
+
+    // Shortest time between two shots
+    pub fn cooldown(self) -> Duration {
+        match self {
+            WeaponKind::Pistol => Duration::from_millis(400),
+            WeaponKind::Shotgun => Duration::from_millis(900),
+            WeaponKind::Rifle => Duration::from_millis(1200),
+        }
+    }
+
+    // Rays per shot
+    pub fn pellets(self) -> u8 {
+        match self {
+            WeaponKind::Shotgun => 8,
+            WeaponKind::Pistol | WeaponKind::Rifle => 1,
+        }
+    }
+
+    // Angle between the aim and the outer pellets, in radians
+    pub fn spread(self) -> f32 {
+        match self {
+            WeaponKind::Shotgun => 0.08,
+            WeaponKind::Pistol | WeaponKind::Rifle => 0.0,
+        }
+    }
+
+    // One normalized direction per pellet, the first along the aim and the
+    // rest around it on two rings. The pattern is fixed, so the client draws
+    // the pellets where the server sends them. Empty for an aim without a
+    // direction.
+    pub fn pellet_directions(self, aim: ChannelVector3) -> Vec<ChannelVector3> {
+        let Some(forward) = aim.normalized() else {
+            return Vec::new();
+        };
+        let up = ChannelVector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        // Straight up or down any horizontal axis will do
+        let right = forward.cross(up).normalized().unwrap_or(ChannelVector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        });
+
+        let pellets = self.pellets().max(1) as usize;
+        let spread = self.spread();
+        let mut directions = vec![forward];
+        for i in 1..pellets {
+            let ring = if i % 2 == 0 { spread / 2.0 } else { spread };
+            let around = std::f32::consts::TAU * i as f32 / (pellets - 1) as f32;
+            let direction = forward.rotated(right, ring).rotated(forward, around);
+            directions.push(direction.normalized().unwrap_or(forward));
+        }
+        directions
+    }
+
+    // Nothing further away is hit
+    pub fn range(self) -> f32 {
+        match self {
+            WeaponKind::Pistol => 40.0,
+            WeaponKind::Shotgun => 15.0,
+            WeaponKind::Rifle => 100.0,
+        }
+    }
+
+    pub fn magazine_size(self) -> u8 {
+        match self {
+            WeaponKind::Pistol => 12,
+            WeaponKind::Shotgun => 6,
+            WeaponKind::Rifle => 5,
+        }
+    }
+
+    // Spare rounds a player starts a match with
+    pub fn starting_reserve(self) -> u16 {
+        match self {
+            WeaponKind::Pistol => 36,
+            WeaponKind::Shotgun => 18,
+            WeaponKind::Rifle => 15,
+        }
+    }
+
+    pub fn reload_time(self) -> Duration {
+        match self {
+            WeaponKind::Pistol => Duration::from_millis(1500),
+            WeaponKind::Shotgun => Duration::from_millis(2500),
+            WeaponKind::Rifle => Duration::from_millis(2000),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Ammo {
+    kind: WeaponKind,
+    pub magazine: u8,
+    pub reserve: u16,
+    // Seconds until the reload is done, None when not reloading
+    reload_left: Option<f32>,
+}
+
+impl Ammo {
+    pub fn new(kind: WeaponKind) -> Self {
+        Ammo {
+            kind,
+            magazine: kind.magazine_size(),
+            reserve: kind.starting_reserve(),
+            reload_left: None,
+        }
+    }
+
+    pub fn can_fire(&self) -> bool {
+        self.magazine > 0 && self.reload_left.is_none()
+    }
+
+    // Returns whether a round was fired
+    pub fn fire(&mut self) -> bool {
+        if !self.can_fire() {
+            return false;
+        }
+        self.magazine -= 1;
+        true
+    }
+
+    // Returns whether a reload started, there is nothing to do with a full
+    // magazine or an empty reserve
+    pub fn start_reload(&mut self) -> bool {
+        if self.reload_left.is_some()
+            || self.reserve == 0
+            || self.magazine >= self.kind.magazine_size()
+        {
+            return false;
+        }
+        self.reload_left = Some(self.kind.reload_time().as_secs_f32());
+        true
+    }
+
+    // Move time forward by one frame, the magazine is refilled once the
+    // reload time has passed
+    pub fn advance(&mut self, dt: f32) {
+        let Some(left) = self.reload_left else {
+            return;
+        };
+        let left = left - dt;
+        if left > 0.0 {
+            self.reload_left = Some(left);
+            return;
+        }
+
+        let missing = (self.kind.magazine_size() - self.magazine) as u16;
+        let loaded = missing.min(self.reserve);
+        self.magazine += loaded as u8;
+        self.reserve -= loaded;
+        self.reload_left = None;
+    }
+
+    // Putting the weapon away stops the reload, the magazine stays as it was
+    pub fn cancel_reload(&mut self) {
+        self.reload_left = None;
+    }
+
+    // From 0 when the reload starts to 1 when it is done
+    pub fn reload_progress(&self) -> Option<f32> {
+        self.reload_left
+            .map(|left| 1.0 - left / self.kind.reload_time().as_secs_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pellets_spread_around_the_aim() {
+        let aim = ChannelVector3 {
+            x: 1.0,
+            y: 0.5,
+            z: -2.0,
+        };
+        let forward = aim.normalized().unwrap();
+        for weapon in WeaponKind::ALL {
+            let directions = weapon.pellet_directions(aim);
+            assert_eq!(directions.len(), weapon.pellets() as usize);
+            assert_eq!(directions[0], forward);
+            for direction in &directions {
+                assert!((direction.length() - 1.0).abs() < 1e-5);
+                let angle = direction.dot(forward).clamp(-1.0, 1.0).acos();
+                assert!(angle <= weapon.spread() + 1e-3, "{:?} {}", weapon, angle);
+            }
+        }
+
+        // Not all on the aim
+        let shotgun = WeaponKind::Shotgun.pellet_directions(aim);
+        assert!(shotgun[1..].iter().all(|direction| *direction != forward));
+    }
+
+    #[test]
+    fn no_pellets_without_an_aim() {
+        let zero = ChannelVector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert!(WeaponKind::Pistol.pellet_directions(zero).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::session;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use game_channel::Rules;
+use serde::{Deserialize, Deserializer};
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+// ----------------------------------------------------------------------------
+// Server settings
+// Read from an optional TOML file, then overridden by command-line flags.
+// ----------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    // Played in this order, one match after the other. Older configs name a
+    // single file as `map`
+    #[serde(alias = "map", deserialize_with = "one_or_many")]
+    pub maps: Vec<PathBuf>,
+    pub players_per_match: usize,
+    // Match traffic over UDP instead of the TCP connection
+    pub udp: bool,
+    // How far back in milliseconds shots are checked against, to make up for
+    // the shooter's latency
+    pub max_rewind: u32,
+    // Seconds players get to vote for the next map after a match, 0 turns
+    // voting off
+    pub vote_time: u8,
+    // Sent to the clients when a match starts
+    pub rules: Rules,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: String::from("0.0.0.0:1234"),
+            maps: vec![PathBuf::from("./resources/map.txt")],
+            players_per_match: 2,
+            udp: false,
+            max_rewind: 250,
+            vote_time: 10,
+            rules: Rules::default(),
+        }
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Game server for shooter-rs")]
+struct Args {
+    /// TOML config file, flags take precedence over its values
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, e.g. 0.0.0.0:1234
+    #[arg(short, long)]
+    bind: Option<String>,
+
+    /// Map file sent to the clients, repeat to rotate through several
+    #[arg(short, long = "map")]
+    maps: Vec<PathBuf>,
+
+    /// Players needed to start a match
+    #[arg(short, long)]
+    players: Option<usize>,
+
+    /// Send match traffic over UDP, the TCP connection only carries the handshake
+    #[arg(long)]
+    udp: bool,
+
+    /// Furthest back shots are checked against player positions, in milliseconds
+    #[arg(long)]
+    max_rewind: Option<u32>,
+
+    /// Seconds to vote for the next map after a match, 0 turns voting off
+    #[arg(long)]
+    vote_time: Option<u8>,
+
+    /// Countdown before a match starts, in seconds
+    #[arg(long)]
+    countdown: Option<u8>,
+
+    /// Health every player starts a match with
+    #[arg(long)]
+    health: Option<u8>,
+
+    /// Health lost per pistol hit, the other weapons scale from it
+    #[arg(long)]
+    damage: Option<u8>,
+
+    /// Server updates per second
+    #[arg(long)]
+    tick_rate: Option<u32>,
+
+    /// How far behind the newest snapshot clients draw other players, in milliseconds
+    #[arg(long)]
+    interpolation_delay: Option<u32>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let args = Args::parse();
+
+        let mut config = match &args.config {
+            Some(path) => {
+                let text = read_to_string(path)
+                    .with_context(|| format!("Reading config {} failed", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("Parsing config {} failed", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(bind) = args.bind {
+            config.bind = bind;
+        }
+        if !args.maps.is_empty() {
+            config.maps = args.maps;
+        }
+        if let Some(players) = args.players {
+            config.players_per_match = players;
+        }
+        if args.udp {
+            config.udp = true;
+        }
+        if let Some(max_rewind) = args.max_rewind {
+            config.max_rewind = max_rewind;
+        }
+        if let Some(vote_time) = args.vote_time {
+            config.vote_time = vote_time;
+        }
+        if let Some(countdown) = args.countdown {
+            config.rules.countdown = countdown;
+        }
+        if let Some(health) = args.health {
+            config.rules.starting_health = health;
+        }
+        if let Some(damage) = args.damage {
+            config.rules.damage = damage;
+        }
+        if let Some(tick_rate) = args.tick_rate {
+            config.rules.tick_rate = tick_rate;
+        }
+        if let Some(delay) = args.interpolation_delay {
+            config.rules.interpolation_delay = delay;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.maps.is_empty() {
+            bail!("At least one map is needed");
+        }
+        if !(2..=session::MAX_PLAYERS).contains(&self.players_per_match) {
+            bail!(
+                "Players per match must be between 2 and {}",
+                session::MAX_PLAYERS
+            );
+        }
+        if self.rules.starting_health == 0 {
+            bail!("Starting health must be above 0");
+        }
+        if self.rules.tick_rate == 0 {
+            bail!("Tick rate must be above 0");
+        }
+        Ok(())
+    }
+}
@@ -1,189 +1,146 @@
-use anyhow::{Context, Result};
-use game_channel::error::ChannelError;
-use game_channel::{Channel, ChannelVector2, Packet};
-use std::io::ErrorKind;
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::{thread, time};
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("0.0.0.0:1234")?;
-    let mut player_1 = Player::new_player_1();
-    let mut player_2 = Player::new_player_2();
+use anyhow::{bail, Context, Result};
+use config::Config;
+use game_channel::udp::UdpChannel;
+use game_channel::{Channel, MapInfo, DEFAULT_MAX_FRAME_SIZE};
+use game_core::map::Layout;
+use matchmaking::MatchManager;
+use rotation::{Rotation, RotationMap};
+use session::Connection;
+use std::fs::read_to_string;
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+mod config;
+mod matchmaking;
+mod rotation;
+mod session;
+
+const WAITING_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+// Leaves room in the handshake reply for everything besides the map file
+const MAX_MAP_SIZE: usize = DEFAULT_MAX_FRAME_SIZE / 2;
+
+fn main() -> Result<()> {
+    let config = Config::load()?;
+    let listener = TcpListener::bind(&config.bind)
+        .with_context(|| format!("Binding {} failed", config.bind))?;
+    let maps = config
+        .maps
+        .iter()
+        .map(|path| load_map(path))
+        .collect::<Result<Vec<_>>>()?;
     eprintln!("Game server started on {}", listener.local_addr().unwrap());
-
-    eprintln!("Waiting for new session...");
-
-    while let Ok(conn) = listener.accept() {
-        eprintln!("Session started!");
-        if let Err(e) = handle_session(&listener, conn, &mut player_1, &mut player_2) {
-            eprintln!("{}", e);
-        }
-        eprintln!("Session over.");
-        player_1.reset_player_1();
-        player_2.reset_player_2();
-        eprintln!("Waiting for new session...");
+    for map in &maps {
+        eprintln!("Map {} loaded", map.layout.name);
     }
-    Ok(())
-}
-
-fn handle_session(
-    listener: &TcpListener,
-    conn: (TcpStream, SocketAddr),
-    player_1: &mut Player,
-    player_2: &mut Player,
-) -> Result<()> {
-    let (s1, addr) = conn;
-    s1.set_nodelay(true)?;
-    eprintln!("Connection from {}", addr);
-    let mut c1 = Channel::with_stream(s1);
-
-    // Send player 1 initial position
-    player_1.write_pos(&mut c1)?;
-    eprintln!("Sent player 1 initial position");
-
-    // Wait for connection from player 2
-    let (s2, addr) = listener.accept()?;
-    s2.set_nodelay(true)?;
-    eprintln!("Connection from {}", addr);
-    let mut c2 = Channel::with_stream(s2);
-
-    // Send player 2 initial position
-    player_2.write_pos(&mut c2)?;
-    eprintln!("Sent player 2 initial position");
-
-    // Send enemy position
-    // This will make the client go into countdown state
-    player_2.write_pos(&mut c1)?;
-    player_1.write_pos(&mut c2)?;
-
-    // Countdown
-    c1.send(Packet::Time(3)).context("Sending time failed")?;
-    c2.send(Packet::Time(3)).context("Sending time failed")?;
-    thread::sleep(time::Duration::from_secs(1));
-    c1.send(Packet::Time(2)).context("Sending time failed")?;
-    c2.send(Packet::Time(2)).context("Sending time failed")?;
-    thread::sleep(time::Duration::from_secs(1));
-    c1.send(Packet::Time(1)).context("Sending time failed")?;
-    c2.send(Packet::Time(1)).context("Sending time failed")?;
-    thread::sleep(time::Duration::from_secs(1));
-
-    // Send 0 to signal start of game
-    c1.send(Packet::Time(0)).context("Sending time failed")?;
-    c2.send(Packet::Time(0)).context("Sending time failed")?;
-
-    let mut s1_closed = false;
-    let mut s2_closed = false;
-
-    // Send players current position and receive their next position
+    let rotation = Arc::new(Mutex::new(Rotation::new(maps)));
+
+    // Connections are accepted and greeted on their own threads, the manager
+    // only ever sees players that completed the handshake
+    let (sender, receiver) = mpsc::channel();
+    let udp = config.udp;
+    let joining = Arc::clone(&rotation);
+    thread::spawn(move || accept_players(listener, sender, udp, joining));
+
+    let mut manager = MatchManager::new(
+        rotation,
+        config.players_per_match,
+        config.rules,
+        Duration::from_millis(config.max_rewind as u64),
+        Duration::from_secs(config.vote_time as u64),
+    );
+    eprintln!("Waiting for players...");
     loop {
-        if s1_closed && s2_closed {
-            break;
-        }
-
-        // Send enemies
-        if let Err(ChannelError::Io(_)) = player_2.write_pos(&mut c1) {
-            s1_closed = true;
-        }
-        if let Err(ChannelError::Io(_)) = player_1.write_pos(&mut c2) {
-            s2_closed = true;
-        }
-
-        // Send players health
-        if let Err(ChannelError::Io(_)) = player_1.write_health(&mut c1) {
-            s1_closed = true;
-        }
-        if let Err(ChannelError::Io(_)) = player_2.write_health(&mut c2) {
-            s2_closed = true;
-        }
-
-        // Receive players
-        if let Err(ChannelError::Io(_)) = player_1.read_pos(&mut c1) {
-            s1_closed = true;
-        }
-        if let Err(ChannelError::Io(_)) = player_2.read_pos(&mut c2) {
-            s2_closed = true;
+        match receiver.recv_timeout(WAITING_UPDATE_INTERVAL) {
+            Ok((channel, map)) => manager.add_player(channel, map),
+            Err(RecvTimeoutError::Timeout) => manager.update_waiting(),
+            Err(RecvTimeoutError::Disconnected) => break,
         }
-
-        // Receive enemies health
-        if let Err(ChannelError::Io(e)) = player_2.read_health(&mut c1) {
-            if e.kind() == ErrorKind::ConnectionAborted {
-                s1_closed = true;
-            }
-        }
-        if let Err(ChannelError::Io(e)) = player_1.read_health(&mut c2) {
-            if e.kind() == ErrorKind::ConnectionAborted {
-                s2_closed = true;
-            }
-        }
-
-        // eprintln!("player_1: {:?}", player_1.pos);
-        // eprintln!("player_2: {:?}", player_2.pos);
     }
     Ok(())
 }
 
-struct Player {
-    pos: ChannelVector2,
-    target: ChannelVector2,
-    health: u8,
-}
-
-impl Player {
-    fn new_player_1() -> Self {
-        Player {
-            pos: ChannelVector2 { x: 0.0, z: 18.0 },
-            target: ChannelVector2 { x: 0.0, z: -1.0 },
-            health: 100,
-        }
-    }
-
-    fn reset_player_1(&mut self) {
-        self.pos = ChannelVector2 { x: 0.0, z: 18.0 };
-        self.target = ChannelVector2 { x: 0.0, z: -1.0 };
-        self.health = 100;
-    }
-
-    fn new_player_2() -> Self {
-        Player {
-            pos: ChannelVector2 { x: 0.0, z: -18.0 },
-            target: ChannelVector2 { x: 0.0, z: 1.0 },
-            health: 100,
-        }
-    }
-
-    fn reset_player_2(&mut self) {
-        self.pos = ChannelVector2 { x: 0.0, z: -18.0 };
-        self.target = ChannelVector2 { x: 0.0, z: 1.0 };
-        self.health = 100;
-    }
-
-    fn write_pos(&self, channel: &mut Channel<TcpStream>) -> Result<(), ChannelError> {
-        channel.send(Packet::Player {
-            pos: self.pos,
-            target: self.target,
-        })
+// Walls, blocks and spawns, and the map file itself to hand to every client
+fn load_map(path: &Path) -> Result<RotationMap> {
+    let source =
+        read_to_string(path).with_context(|| format!("Reading map {} failed", path.display()))?;
+    if source.len() > MAX_MAP_SIZE {
+        bail!(
+            "Map {} is {} bytes, at most {} fit in the handshake",
+            path.display(),
+            source.len(),
+            MAX_MAP_SIZE
+        );
     }
+    let layout =
+        Layout::parse(&source).with_context(|| format!("Loading map {} failed", path.display()))?;
+    let info = MapInfo::new(&layout.name, &source);
+    Ok(RotationMap::new(layout, info))
+}
 
-    fn read_pos(&mut self, channel: &mut Channel<TcpStream>) -> Result<(), ChannelError> {
-        channel.receive().and_then(|packet| {
-            if let Packet::Player { pos, target } = packet {
-                self.pos = pos;
-                self.target = target;
+fn accept_players(
+    listener: TcpListener,
+    players: Sender<(Connection, Arc<RotationMap>)>,
+    udp: bool,
+    rotation: Arc<Mutex<Rotation>>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Accepting connection failed: {}", e);
+                continue;
             }
-            Err(ChannelError::Bincode)
-        })
-    }
+        };
 
-    fn write_health(&self, channel: &mut Channel<TcpStream>) -> Result<(), ChannelError> {
-        channel.send(Packet::Health(self.health))
+        let players = players.clone();
+        let map = rotation.lock().unwrap().current();
+        thread::spawn(move || match greet_player(stream, udp, &map.info) {
+            Ok(channel) => {
+                let _ = players.send((channel, map));
+            }
+            Err(e) => eprintln!("{:#}", e),
+        });
     }
+}
 
-    fn read_health(&mut self, channel: &mut Channel<TcpStream>) -> Result<(), ChannelError> {
-        channel.receive().and_then(|packet| {
-            if let Packet::Health(health) = packet {
-                self.health = health;
-            }
-            Err(ChannelError::Bincode)
-        })
+fn greet_player(stream: TcpStream, udp: bool, map: &MapInfo) -> Result<Connection> {
+    let addr = stream.peer_addr()?;
+    eprintln!("Connection from {}", addr);
+    stream.set_nodelay(true)?;
+
+    // The client is told where to find its own UDP socket in the handshake reply
+    let socket = match udp {
+        true => Some(UdpSocket::bind((stream.local_addr()?.ip(), 0))?),
+        false => None,
+    };
+    let udp_port = match &socket {
+        Some(socket) => Some(socket.local_addr()?.port()),
+        None => None,
+    };
+
+    // Don't let a silent client hold on to a thread forever
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut channel = Channel::with_stream(stream);
+    let handshake = channel
+        .accept_handshake(udp_port, map)
+        .with_context(|| format!("Handshake with {} failed", addr))?;
+    channel.stream.set_read_timeout(None)?;
+
+    eprintln!(
+        "{} joined from {} with client {}",
+        handshake.player_name, addr, handshake.client_build
+    );
+
+    match socket {
+        Some(socket) => {
+            let channel = UdpChannel::accept(socket, channel.stream)
+                .with_context(|| format!("UDP setup with {} failed", addr))?;
+            Ok(Box::new(channel))
+        }
+        None => Ok(Box::new(channel)),
     }
 }
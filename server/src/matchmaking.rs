@@ -0,0 +1,130 @@
+use crate::rotation::{Rotation, RotationMap};
+use crate::session::{self, Connection, Player};
+use game_channel::{Rules, Winner};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// ----------------------------------------------------------------------------
+// Groups players as they arrive and runs every match on its own thread
+// ----------------------------------------------------------------------------
+
+pub struct MatchManager {
+    waiting: Vec<Player>,
+    players_per_match: usize,
+    rules: Rules,
+    max_rewind: Duration,
+    vote_time: Duration,
+    rotation: Arc<Mutex<Rotation>>,
+    matches_started: usize,
+}
+
+impl MatchManager {
+    pub fn new(
+        rotation: Arc<Mutex<Rotation>>,
+        players_per_match: usize,
+        rules: Rules,
+        max_rewind: Duration,
+        vote_time: Duration,
+    ) -> Self {
+        MatchManager {
+            waiting: Vec::new(),
+            players_per_match,
+            rules,
+            max_rewind,
+            vote_time,
+            rotation,
+            matches_started: 0,
+        }
+    }
+
+    // Called regularly while waiting for players to arrive
+    pub fn update_waiting(&mut self) {
+        self.move_to_current_map();
+        self.waiting.iter_mut().for_each(Player::flush);
+        self.waiting.retain(Player::still_connected);
+    }
+
+    // The map may have changed since the players got it, by a vote or a match
+    // starting while they were joining
+    fn move_to_current_map(&mut self) {
+        let map = self.rotation.lock().unwrap().current();
+        move_to_map(&mut self.waiting, &map);
+    }
+
+    // The map is the one the player got in the handshake
+    pub fn add_player(&mut self, channel: Connection, map: Arc<RotationMap>) {
+        self.waiting.retain(Player::still_connected);
+
+        // Reuse the ids of players that gave up waiting
+        let id = (0..)
+            .find(|id| self.waiting.iter().all(|player| player.id() != *id))
+            .unwrap_or_default();
+
+        // The client waits for enemies once it knows its starting position
+        let spawn = map.layout.spawn(id as usize);
+        match Player::spawn(id, channel, map, spawn, self.rules.starting_health) {
+            Ok(player) => self.waiting.push(player),
+            Err(e) => {
+                eprintln!("Sending initial position failed: {}", e);
+                return;
+            }
+        }
+        eprintln!(
+            "Player {} waiting, {}/{} players",
+            id,
+            self.waiting.len(),
+            self.players_per_match
+        );
+
+        if self.waiting.len() >= self.players_per_match {
+            let players = std::mem::take(&mut self.waiting);
+            self.start_match(players);
+        }
+    }
+
+    fn start_match(&mut self, mut players: Vec<Player>) {
+        self.matches_started += 1;
+        let id = self.matches_started;
+        // Taken in the same lock the rotation moves on in, so a vote closing
+        // on another match cannot put these players on a different map
+        let map = self.rotation.lock().unwrap().start_match();
+        move_to_map(&mut players, &map);
+        let rotation = Arc::clone(&self.rotation);
+        let rules = self.rules;
+        let max_rewind = self.max_rewind;
+        let vote_time = self.vote_time;
+
+        thread::spawn(move || {
+            eprintln!(
+                "[match {}] Started with {} players on {}",
+                id,
+                players.len(),
+                map.layout.name
+            );
+            match session::run_match(&mut players, &map.obstacles, rules, max_rewind) {
+                Ok(Winner::Player(winner)) => eprintln!("[match {}] Player {} won", id, winner),
+                Ok(Winner::Forfeit(winner)) => {
+                    eprintln!("[match {}] Player {} won by forfeit", id, winner)
+                }
+                Ok(Winner::None) => eprintln!("[match {}] Nobody survived", id),
+                Err(e) => eprintln!("[match {}] {:#}", id, e),
+            }
+            if !vote_time.is_zero() {
+                let next = session::run_vote(&mut players, &rotation, vote_time);
+                eprintln!("[match {}] Next map is {}", id, next);
+            }
+            players.iter_mut().for_each(Player::finish);
+            eprintln!("[match {}] Over", id);
+        });
+    }
+}
+
+fn move_to_map(players: &mut [Player], map: &Arc<RotationMap>) {
+    for player in players.iter_mut() {
+        if !Arc::ptr_eq(player.map(), map) {
+            let spawn = map.layout.spawn(player.id() as usize);
+            player.change_map(Arc::clone(map), spawn);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use game_channel::MapInfo;
+use game_core::geometry::Aabb;
+use game_core::map::Layout;
+use std::sync::Arc;
+
+// ----------------------------------------------------------------------------
+// Map rotation
+// Players get the current map when they join and the next match is played on
+// it. Every match that starts moves the rotation on, unless the players of the
+// last match voted for a map, which then comes up next. Matches running at
+// the same time share the rotation, so the vote that closes last decides.
+// ----------------------------------------------------------------------------
+
+pub struct RotationMap {
+    pub layout: Layout,
+    // Walls and blocks, worked out once for every match on this map
+    pub obstacles: Vec<Aabb>,
+    // Sent to the clients
+    pub info: MapInfo,
+}
+
+impl RotationMap {
+    pub fn new(layout: Layout, info: MapInfo) -> Self {
+        RotationMap {
+            obstacles: layout.obstacles(),
+            layout,
+            info,
+        }
+    }
+}
+
+pub struct Rotation {
+    maps: Vec<Arc<RotationMap>>,
+    current: usize,
+}
+
+impl Rotation {
+    // There must be at least one map
+    pub fn new(maps: Vec<RotationMap>) -> Self {
+        assert!(!maps.is_empty(), "Rotation needs a map");
+        Rotation {
+            maps: maps.into_iter().map(Arc::new).collect(),
+            current: 0,
+        }
+    }
+
+    // The map new players get
+    pub fn current(&self) -> Arc<RotationMap> {
+        Arc::clone(&self.maps[self.current])
+    }
+
+    // A match starts on the current map, the players after it get the next one
+    pub fn start_match(&mut self) -> Arc<RotationMap> {
+        let map = self.current();
+        self.current = (self.current + 1) % self.maps.len();
+        map
+    }
+
+    // The players voted, out of range votes were turned down before
+    pub fn choose(&mut self, index: usize) {
+        self.current = index;
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.maps
+            .iter()
+            .map(|map| map.layout.name.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,741 @@
+use crate::rotation::{Rotation, RotationMap};
+use anyhow::Result;
+use game_channel::error::ChannelError;
+use game_channel::{ChannelVector2, ChannelVector3, Packet, PlayerId, Rules, Transport, Winner};
+use game_core::geometry::{self, Aabb};
+use game_core::map::Spawn;
+use game_core::movement::{self, Input, MoveState};
+use game_core::weapon::{Ammo, WeaponKind};
+use std::collections::{HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Players share spawns once a map runs out of them
+pub const MAX_PLAYERS: usize = 8;
+
+// A few seconds of snapshots for a full match
+const MAX_PENDING_WRITES: usize = 64 * 1024;
+
+// Inputs are the only way to move, so they are what gets checked. Each input
+// is one movement step, and all inputs together may not get ahead of real time
+// by more than a burst after a lag spike.
+const MAX_INPUT_AHEAD: f64 = 0.5;
+// How far the length of a view direction may be off from 1 before the server
+// normalizes it, rounding leaves a normalized vector this close
+const FACING_TOLERANCE: f32 = 1e-4;
+// Shots are timed by the inputs sent before them, like the client times them,
+// so bunched up packets do not look like a faster fire rate. Allows for
+// rounding in the step times.
+const FIRE_RATE_TOLERANCE: f64 = 0.001;
+// Shots held back until the inputs sent before them arrive, a client with more
+// is not sending those inputs at all
+const MAX_WAITING_SHOTS: usize = 8;
+// Offenders are logged at most this often
+const WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+const FINISH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const VOTE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Every player already knows their own starting position from when they joined
+pub fn run_match(
+    players: &mut [Player],
+    obstacles: &[Aabb],
+    rules: Rules,
+    max_rewind: Duration,
+) -> Result<Winner> {
+    // Snapshots are stamped with the time since this point
+    let start = Instant::now();
+
+    // Send the rules, starting ammo and enemy positions
+    let states: Vec<Packet> = players.iter().map(|player| player.state(0.0)).collect();
+    for player in players.iter_mut() {
+        player.send(Packet::Rules(rules));
+        for weapon in WeaponKind::ALL {
+            player.send_ammo(weapon);
+        }
+        for state in &states {
+            if !player.owns(state) {
+                player.send(state.clone());
+            }
+        }
+    }
+
+    // From here on nothing blocks, a tick only handles what has already arrived
+    players.iter_mut().for_each(Player::go_nonblocking);
+    let tick = Duration::from_secs(1) / rules.tick_rate;
+
+    // Countdown
+    // The first time makes the clients go into countdown state and 0 starts the game
+    for time in (0..=rules.countdown).rev() {
+        players
+            .iter_mut()
+            .for_each(|player| player.send(Packet::Time(time)));
+        if time > 0 {
+            let second = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < second {
+                thread::sleep(tick);
+                players.iter_mut().for_each(Player::flush);
+            }
+        }
+    }
+
+    let mut gone = HashSet::new();
+    let mut standing: Vec<PlayerId> = players.iter().map(|player| player.id).collect();
+    let mut next_tick = Instant::now();
+
+    let winner = loop {
+        // Apply every input that arrived since the last tick
+        let now = start.elapsed().as_secs_f64();
+        let shots: Vec<Vec<Shot>> = players
+            .iter_mut()
+            .map(|player| player.read_input(obstacles))
+            .collect();
+        for player in players.iter_mut() {
+            player.record_box(now, max_rewind);
+        }
+
+        // Shots are checked against the players where the shooter saw them,
+        // which is one interpolation delay behind their newest snapshot
+        let delay = Duration::from_millis(rules.interpolation_delay as u64).as_secs_f64();
+        let earliest = now - max_rewind.as_secs_f64();
+        for (shooter, shots) in shots.iter().enumerate() {
+            for shot in shots {
+                let seen = (shot.time - delay).clamp(earliest, now);
+                resolve_shot(players, shooter, shot, seen, obstacles, rules.damage);
+            }
+        }
+
+        broadcast_departures(players, &mut gone);
+        broadcast_snapshot(players, now);
+        if let Some(winner) = decide_winner(players, &mut standing) {
+            break winner;
+        }
+
+        // Sleep until the next tick, without trying to catch up on missed ones
+        next_tick += tick;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else {
+            next_tick = now;
+        }
+    };
+
+    for player in players.iter_mut() {
+        player.send(Packet::GameOver(winner.clone()));
+    }
+    Ok(winner)
+}
+
+// After a match the players pick the next map from the rotation. A player may
+// change their vote, it ends when time is up or everyone still there voted.
+// Returns the name of the map that comes up next.
+pub fn run_vote(players: &mut [Player], rotation: &Mutex<Rotation>, duration: Duration) -> String {
+    let maps = rotation.lock().unwrap().names();
+    if maps.len() < 2 {
+        return rotation.lock().unwrap().current().layout.name.clone();
+    }
+
+    let seconds = duration.as_secs().min(u8::MAX as u64) as u8;
+    for player in players.iter_mut() {
+        player.send(Packet::MapVote {
+            maps: maps.clone(),
+            seconds,
+        });
+    }
+
+    let deadline = Instant::now() + duration;
+    let mut votes: Vec<Option<usize>> = vec![None; players.len()];
+    while Instant::now() < deadline {
+        let mut changed = false;
+        for (player, vote) in players.iter_mut().zip(votes.iter_mut()) {
+            if let Some(choice) = player.read_vote(maps.len()) {
+                changed |= *vote != Some(choice);
+                *vote = Some(choice);
+            }
+        }
+
+        if changed {
+            let mut counts = vec![0u8; maps.len()];
+            for &choice in votes.iter().flatten() {
+                counts[choice] = counts[choice].saturating_add(1);
+            }
+            for player in players.iter_mut() {
+                player.send(Packet::Votes(counts.clone()));
+            }
+        }
+        players.iter_mut().for_each(Player::flush);
+
+        let everyone_voted = players
+            .iter()
+            .zip(&votes)
+            .all(|(player, vote)| !player.connected || vote.is_some());
+        if everyone_voted {
+            break;
+        }
+        thread::sleep(VOTE_POLL_INTERVAL);
+    }
+
+    // Ties go to the map listed first, no votes at all keep the rotation going
+    let mut counts = vec![0usize; maps.len()];
+    for &choice in votes.iter().flatten() {
+        counts[choice] += 1;
+    }
+    let next = {
+        let mut rotation = rotation.lock().unwrap();
+        if let Some(best) = counts.iter().max().filter(|&&count| count > 0) {
+            let choice = counts.iter().position(|count| count == best).unwrap();
+            rotation.choose(choice);
+        }
+        rotation.current().layout.name.clone()
+    };
+    for player in players.iter_mut() {
+        player.send(Packet::NextMap(next.clone()));
+    }
+    next
+}
+
+// The match is over once at most one player is left standing. If the last
+// opponents left instead of dying, the survivor wins by forfeit.
+fn decide_winner(players: &[Player], standing: &mut Vec<PlayerId>) -> Option<Winner> {
+    let forfeited = players
+        .iter()
+        .any(|player| !player.connected && standing.contains(&player.id));
+    *standing = players
+        .iter()
+        .filter(|player| player.connected && player.health > 0)
+        .map(|player| player.id)
+        .collect();
+
+    match standing.as_slice() {
+        [] => Some(Winner::None),
+        [id] if forfeited => Some(Winner::Forfeit(*id)),
+        [id] => Some(Winner::Player(*id)),
+        _ => None,
+    }
+}
+
+// Tell everyone about players that left since the last tick
+fn broadcast_departures(players: &mut [Player], gone: &mut HashSet<PlayerId>) {
+    let left: Vec<PlayerId> = players
+        .iter()
+        .filter(|player| !player.connected && gone.insert(player.id))
+        .map(|player| player.id)
+        .collect();
+    for id in left {
+        eprintln!("Player {} left", id);
+        players
+            .iter_mut()
+            .for_each(|player| player.send(Packet::Left(id)));
+    }
+}
+
+// Send everyone their own position, their enemies, and health that changed
+// since the last tick
+fn broadcast_snapshot(players: &mut [Player], time: f64) {
+    let states: Vec<Packet> = players
+        .iter()
+        .filter(|player| player.connected)
+        .map(|player| player.state(time))
+        .collect();
+    let healths: Vec<Packet> = players
+        .iter_mut()
+        .filter(|player| player.health_changed)
+        .map(|player| {
+            player.health_changed = false;
+            Packet::Health {
+                id: player.id,
+                health: player.health,
+            }
+        })
+        .collect();
+    for player in players.iter_mut() {
+        player.send_snapshot(Packet::Position {
+            sequence: player.last_input,
+            state: player.movement,
+        });
+        for state in &states {
+            if !player.owns(state) {
+                player.send_snapshot(state.clone());
+            }
+        }
+        for health in &healths {
+            player.send(health.clone());
+        }
+    }
+}
+
+// Every pellet hits the nearest living enemy along its ray within the weapon's
+// range, unless the map is in the way
+fn resolve_shot(
+    players: &mut [Player],
+    shooter: usize,
+    shot: &Shot,
+    seen: f64,
+    obstacles: &[Aabb],
+    damage: u8,
+) {
+    if players[shooter].health == 0 {
+        return;
+    }
+
+    let targets: Vec<usize> = (0..players.len())
+        .filter(|&i| i != shooter && players[i].connected && players[i].health > 0)
+        .collect();
+    let boxes: Vec<Aabb> = targets.iter().map(|&i| players[i].box_at(seen)).collect();
+    let damage = shot.weapon.damage(damage);
+
+    // The directions are normalized, so the range is a distance
+    for &direction in &shot.directions {
+        let range = shot.weapon.range();
+        if let Some(hit) = geometry::first_hit(shot.origin, direction, range, &boxes, obstacles) {
+            let shooter = players[shooter].id;
+            let target = &mut players[targets[hit]];
+            target.health = target.health.saturating_sub(damage);
+            target.health_changed = true;
+            eprintln!(
+                "Player {} hit player {} with the {}, health {}",
+                shooter,
+                target.id,
+                shot.weapon.name().to_lowercase(),
+                target.health
+            );
+        }
+    }
+}
+
+pub type Connection = Box<dyn Transport + Send>;
+
+pub struct Player {
+    id: PlayerId,
+    channel: Connection,
+    connected: bool,
+    // The map the client has, the server moves waiting players along with the
+    // rotation
+    map: Arc<RotationMap>,
+    movement: MoveState,
+    target: ChannelVector2,
+    // Sequence of the last input applied, 0 before the first one
+    last_input: u32,
+    // Bounding box at every recent tick, oldest first
+    history: VecDeque<(f64, Aabb)>,
+    // Seconds of movement the player may still send, refilled in real time
+    input_budget: f64,
+    budget_updated: Option<Instant>,
+    // Sum of the frame times of every input applied
+    input_clock: f64,
+    // One of each weapon, by WeaponKind::index
+    weapons: Vec<Loadout>,
+    equipped: WeaponKind,
+    // Shots that arrived before the inputs sent ahead of them, by the
+    // sequence of the last one of those inputs
+    waiting_shots: VecDeque<(u32, Shot)>,
+    rejected_packets: u32,
+    last_warning: Option<Instant>,
+    health: u8,
+    health_changed: bool,
+}
+
+// What the player carries of one weapon
+struct Loadout {
+    ammo: Ammo,
+    // Input clock at the last shot
+    last_shot: Option<f64>,
+}
+
+struct Shot {
+    weapon: WeaponKind,
+    // Where the server has the shooter, not where the client says
+    origin: ChannelVector3,
+    // Spread around the client's aim here, so the pellets cannot be bunched
+    directions: Vec<ChannelVector3>,
+    time: f64,
+}
+
+impl Player {
+    // Send the player their id and starting position
+    pub fn spawn(
+        id: PlayerId,
+        channel: Connection,
+        map: Arc<RotationMap>,
+        spawn: Option<Spawn>,
+        health: u8,
+    ) -> Result<Self, ChannelError> {
+        let (movement, target) = starting_point(spawn);
+        let mut player = Player {
+            id,
+            channel,
+            connected: true,
+            map,
+            movement,
+            target,
+            last_input: 0,
+            history: VecDeque::new(),
+            input_budget: MAX_INPUT_AHEAD,
+            budget_updated: None,
+            input_clock: 0.0,
+            weapons: WeaponKind::ALL
+                .iter()
+                .map(|&weapon| Loadout {
+                    ammo: Ammo::new(weapon),
+                    last_shot: None,
+                })
+                .collect(),
+            equipped: WeaponKind::Pistol,
+            waiting_shots: VecDeque::new(),
+            rejected_packets: 0,
+            last_warning: None,
+            health,
+            health_changed: false,
+        };
+        player.channel.send(player.state(0.0))?;
+        Ok(player)
+    }
+
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+
+    pub fn map(&self) -> &Arc<RotationMap> {
+        &self.map
+    }
+
+    // Hand a waiting player the map the next match is on and where they start
+    pub fn change_map(&mut self, map: Arc<RotationMap>, spawn: Option<Spawn>) {
+        (self.movement, self.target) = starting_point(spawn);
+        self.send(Packet::Map(map.info.clone()));
+        self.map = map;
+        self.send(self.state(0.0));
+    }
+
+    // A waiting client may have given up before the match started
+    pub fn still_connected(&self) -> bool {
+        self.connected && self.channel.is_open()
+    }
+
+    // Keeps unacknowledged packets moving while nothing new is sent
+    pub fn flush(&mut self) {
+        if self.connected && self.channel.flush().is_err() {
+            self.connected = false;
+        }
+    }
+
+    fn state(&self, time: f64) -> Packet {
+        Packet::Player {
+            id: self.id,
+            pos: self.movement.pos,
+            target: self.target,
+            time,
+            weapon: self.equipped,
+        }
+    }
+
+    fn owns(&self, packet: &Packet) -> bool {
+        matches!(packet, Packet::Player { id, .. } if *id == self.id)
+    }
+
+    fn go_nonblocking(&mut self) {
+        if self.channel.set_nonblocking(true).is_err() {
+            self.connected = false;
+        }
+    }
+
+    // Give the last packets a moment to arrive before the connection is dropped
+    pub fn finish(&mut self) {
+        let deadline = Instant::now() + FINISH_TIMEOUT;
+        while self.connected && self.channel.pending_writes() > 0 && Instant::now() < deadline {
+            self.flush();
+            thread::sleep(FINISH_POLL_INTERVAL);
+        }
+    }
+
+    fn send(&mut self, packet: Packet) {
+        self.deliver(packet, true);
+    }
+
+    // Every tick sends new ones, so a lost one does not have to be resent
+    fn send_snapshot(&mut self, packet: Packet) {
+        self.deliver(packet, false);
+    }
+
+    // Sends are buffered, a client that stops reading is dropped rather than
+    // letting its backlog grow forever
+    fn deliver(&mut self, packet: Packet, reliable: bool) {
+        if !self.connected {
+            return;
+        }
+        let sent = if reliable {
+            self.channel.send(packet)
+        } else {
+            self.channel.send_unreliable(packet)
+        };
+        match sent {
+            Err(e) => {
+                eprintln!("Sending to player {} failed, dropping them: {}", self.id, e);
+                self.connected = false;
+            }
+            _ if self.channel.pending_writes() > MAX_PENDING_WRITES => {
+                eprintln!("Player {} is not keeping up, dropping them", self.id);
+                self.connected = false;
+            }
+            _ => (),
+        }
+    }
+
+    // Drain everything received so far, moving the player through every input
+    // in order and keeping every shot
+    fn read_input(&mut self, obstacles: &[Aabb]) -> Vec<Shot> {
+        self.refill_input_budget();
+
+        let mut shots = Vec::new();
+        while self.connected {
+            match self.channel.receive() {
+                Ok(Packet::Inputs(inputs)) => {
+                    for input in inputs {
+                        // Every packet repeats the last few inputs, most of
+                        // them were applied before
+                        if input.sequence.wrapping_sub(self.last_input) as i32 <= 0 {
+                            continue;
+                        }
+                        match self.check_input(input) {
+                            Ok(input) => {
+                                self.apply(&input, obstacles);
+                                self.release_shots(&mut shots);
+                            }
+                            Err(reason) => self.reject(reason),
+                        }
+                    }
+                }
+                Ok(Packet::Fire { after, aim, time }) => {
+                    if self.waiting_shots.len() >= MAX_WAITING_SHOTS {
+                        self.reject("shots without the inputs before them");
+                        continue;
+                    }
+                    let shot = Shot {
+                        weapon: self.equipped,
+                        origin: self.eyes(),
+                        directions: self.equipped.pellet_directions(aim),
+                        time,
+                    };
+                    self.waiting_shots.push_back((after, shot));
+                    self.release_shots(&mut shots);
+                }
+                Ok(Packet::Reload) => {
+                    if !self.loadout().ammo.start_reload() {
+                        self.reject("a reload it could not do");
+                        self.send_ammo(self.equipped);
+                    }
+                }
+                Ok(Packet::Equip(weapon)) => {
+                    self.loadout().ammo.cancel_reload();
+                    self.equipped = weapon;
+                }
+                Ok(_) => (),
+                Err(ChannelError::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                // A bad payload still had a valid frame, so the stream is in sync
+                Err(ChannelError::Bincode) => self.reject("a packet that did not decode"),
+                Err(_) => self.connected = false,
+            }
+        }
+        shots
+    }
+
+    // Drain everything received so far, inputs from the end of the match
+    // included, and keep the last valid vote
+    fn read_vote(&mut self, maps: usize) -> Option<usize> {
+        let mut vote = None;
+        while self.connected {
+            match self.channel.receive() {
+                Ok(Packet::Vote(choice)) if (choice as usize) < maps => {
+                    vote = Some(choice as usize)
+                }
+                Ok(Packet::Vote(_)) => self.reject("a vote for a map that is not there"),
+                Ok(_) => (),
+                Err(ChannelError::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ChannelError::Bincode) => self.reject("a packet that did not decode"),
+                Err(_) => self.connected = false,
+            }
+        }
+        vote
+    }
+
+    // Keep just enough history to rewind max_rewind from now
+    fn record_box(&mut self, now: f64, max_rewind: Duration) {
+        self.history
+            .push_back((now, geometry::player_box(self.movement.pos)));
+        let earliest = now - max_rewind.as_secs_f64();
+        while self.history.len() > 1 && self.history[1].0 <= earliest {
+            self.history.pop_front();
+        }
+    }
+
+    // Where the player was at the given time, between the ticks around it
+    fn box_at(&self, time: f64) -> Aabb {
+        match self.history.iter().position(|(tick, _)| *tick > time) {
+            Some(0) => self.history[0].1,
+            Some(i) => {
+                let (from_time, from) = self.history[i - 1];
+                let (to_time, to) = self.history[i];
+                let t = ((time - from_time) / (to_time - from_time)) as f32;
+                Aabb::new(from.min.lerp(to.min, t), from.max.lerp(to.max, t))
+            }
+            None => self.history.back().map_or_else(
+                || geometry::player_box(self.movement.pos),
+                |(_, aabb)| *aabb,
+            ),
+        }
+    }
+
+    fn refill_input_budget(&mut self) {
+        let now = Instant::now();
+        if let Some(updated) = self.budget_updated {
+            let elapsed = now.duration_since(updated).as_secs_f64();
+            self.input_budget = (self.input_budget + elapsed).min(MAX_INPUT_AHEAD);
+        }
+        self.budget_updated = Some(now);
+    }
+
+    // Clamps what can be clamped, the rest would move the player further or
+    // faster than the keys allow. The client is corrected by the next position.
+    fn check_input(&mut self, mut input: Input) -> Result<Input, &'static str> {
+        if (movement::TIMESTEP as f64) > self.input_budget {
+            return Err("inputs faster than real time");
+        }
+
+        // A longer view direction would mean a higher speed. The client
+        // normalized it already, dividing again could change the last bit and
+        // with it the step the client predicted.
+        let facing = input.facing;
+        let length = (facing.x * facing.x + facing.z * facing.z).sqrt();
+        if !length.is_normal() {
+            return Err("a bad view direction");
+        }
+        if (length - 1.0).abs() > FACING_TOLERANCE {
+            input.facing = ChannelVector2 {
+                x: facing.x / length,
+                z: facing.z / length,
+            };
+        }
+
+        self.input_budget -= movement::TIMESTEP as f64;
+        Ok(input)
+    }
+
+    // Shots start here, with every input before them applied
+    fn eyes(&self) -> ChannelVector3 {
+        ChannelVector3 {
+            y: self.movement.pos.y + geometry::EYE_HEIGHT,
+            ..self.movement.pos
+        }
+    }
+
+    fn loadout(&mut self) -> &mut Loadout {
+        &mut self.weapons[self.equipped.index()]
+    }
+
+    // Inputs arrive unreliably and shots reliably, so a shot can get here
+    // first. It is taken once the inputs before it are applied, or skipped
+    // for good, so it starts where the client fired it and counts against the
+    // same clock.
+    fn release_shots(&mut self, shots: &mut Vec<Shot>) {
+        while let Some((after, _)) = self.waiting_shots.front() {
+            if after.wrapping_sub(self.last_input) as i32 > 0 {
+                break;
+            }
+            let Some((_, mut shot)) = self.waiting_shots.pop_front() else {
+                break;
+            };
+            match self.check_shot(&shot) {
+                Ok(()) => {
+                    shot.origin = self.eyes();
+                    shots.push(shot);
+                }
+                Err(reason) => self.reject(reason),
+            }
+        }
+    }
+
+    fn check_shot(&mut self, shot: &Shot) -> Result<(), &'static str> {
+        let weapon = shot.weapon;
+        if shot.directions.is_empty() {
+            return Err("a shot without an aim");
+        }
+        if !self.weapons[weapon.index()].ammo.can_fire() {
+            // The client's count is off, set it straight
+            self.send_ammo(weapon);
+            return Err("a shot from an empty gun");
+        }
+
+        let cooldown = weapon.cooldown().as_secs_f64() - FIRE_RATE_TOLERANCE;
+        let clock = self.input_clock;
+        let loadout = &mut self.weapons[weapon.index()];
+        if loadout
+            .last_shot
+            .is_some_and(|last| clock - last < cooldown)
+        {
+            return Err("shots faster than the fire rate");
+        }
+        loadout.last_shot = Some(clock);
+        loadout.ammo.fire();
+        Ok(())
+    }
+
+    fn send_ammo(&mut self, weapon: WeaponKind) {
+        let ammo = self.weapons[weapon.index()].ammo;
+        self.send(Packet::Ammo {
+            weapon,
+            magazine: ammo.magazine,
+            reserve: ammo.reserve,
+        });
+    }
+
+    fn reject(&mut self, reason: &str) {
+        self.rejected_packets += 1;
+        let now = Instant::now();
+        if self
+            .last_warning
+            .is_none_or(|warned| now.duration_since(warned) >= WARNING_INTERVAL)
+        {
+            eprintln!(
+                "Player {} sent {}, {} packets rejected so far",
+                self.id, reason, self.rejected_packets
+            );
+            self.last_warning = Some(now);
+        }
+    }
+
+    // Same movement code the client predicts with
+    fn apply(&mut self, input: &Input, obstacles: &[Aabb]) {
+        movement::step(&mut self.movement, input, obstacles);
+        self.target = ChannelVector2 {
+            x: self.movement.pos.x + input.facing.x,
+            z: self.movement.pos.z + input.facing.z,
+        };
+        self.last_input = input.sequence;
+        self.input_clock += movement::TIMESTEP as f64;
+        for loadout in self.weapons.iter_mut() {
+            loadout.ammo.advance(movement::TIMESTEP);
+        }
+    }
+}
+
+// On the spawn facing the way it says, or in the middle of a map without spawns
+fn starting_point(spawn: Option<Spawn>) -> (MoveState, ChannelVector2) {
+    let (pos, target) = spawn.map_or(
+        (
+            ChannelVector2 { x: 0.0, z: 0.0 },
+            ChannelVector2 { x: 0.0, z: 1.0 },
+        ),
+        |spawn| (spawn.pos, spawn.target()),
+    );
+    let movement = MoveState::at(ChannelVector3 {
+        x: pos.x,
+        y: 0.0,
+        z: pos.z,
+    });
+    (movement, target)
+}